@@ -1,46 +1,453 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+#[cfg(feature = "std")]
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
 
+#[cfg(feature = "colors")]
 use colored::Colorize;
 use num_bigint::{BigInt, ToBigInt};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
     ExpectedDigit,
+    IncompleteExponent,
     UnknownCharacter,
+    UnreadableSource,
+    UnterminatedRegex,
+    UnterminatedString,
+    UnterminatedHeredoc,
+    UnterminatedBlockComment,
+    NestingDepthExceeded {
+        depth: usize,
+        max: usize,
+    },
+    IdentifierTooLong {
+        length: usize,
+        max: usize,
+    },
+    UnexpectedTokenSequence {
+        after: &'static str,
+        found: &'static str,
+    },
+    ExpectedToken {
+        expected: String,
+        found: String,
+    },
+    AliasSubstituted {
+        canonical: String,
+    },
+    InsertedToken {
+        lexeme: &'static str,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Error,
+    Message,
+    Gutter,
+    Caret,
+    Secondary,
+    Plain,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberClass {
+    Integer,
+    Float,
+    BigInt,
+    Hex,
+    Octal,
+    Binary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PunctuationKind {
+    Comma,
+    Dot,
+    Semicolon,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BracketKind {
+    Paren,
+    Brace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncompleteReason {
+    UnclosedBracket { depth: usize },
+    TrailingOperator,
+    UnterminatedString,
+    UnterminatedHeredoc,
+    UnterminatedBlockComment,
 }
 
 #[derive(Debug)]
-pub enum TokenKind {
-    Bracket,
-    Punctuation,
-    Operator,
+pub enum ScanLineResult<'t, 'a, 'b> {
+    Complete(&'t [Token<'a>]),
+    Incomplete { reason: IncompleteReason },
+    Error(Error<'a, 'b>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SemanticGroupKind {
+    Declaration,
+    Statement,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TreeCursorEvent<'a> {
+    Open(BracketKind),
+    Leaf(&'a Token<'a>),
+    Close(BracketKind),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeHint {
+    Integer,
+    Float,
+    BigInt,
+    String,
+    Bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaintLevel {
+    Untrusted,
+    Trusted,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind<'a> {
+    Bracket(&'a str),
+    Punctuation(char),
+    Operator(&'a str),
+    Eof,
+    DocComment,
+    Whitespace,
+    Number(f64),
+    BigInt(BigInt),
+    RegexLiteral(String),
+    CustomOp(String),
+    Shebang(String),
+    String(String),
+    Identifier(&'a str),
+    Keyword(&'a str),
+    HeredocLiteral { tag: String, content: String },
+    Newline(usize),
+    Spread,
+    Ellipsis,
+    Boolean(bool),
+    Null,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedTokenKind {
+    Bracket(String),
+    Punctuation(char),
+    Operator(String),
     Eof,
+    DocComment,
+    Whitespace,
     Number(f64),
     BigInt(BigInt),
+    RegexLiteral(String),
+    CustomOp(String),
+    Shebang(String),
+    String(String),
+    Identifier(String),
+    Keyword(String),
+    HeredocLiteral { tag: String, content: String },
+    Newline(usize),
+    Spread,
+    Ellipsis,
+    Boolean(bool),
+    Null,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Error<'a, 'b> {
+    inner: Box<ErrorInner<'a, 'b>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ErrorInner<'a, 'b> {
     lexeme: &'a str,
-    line: usize,
-    column: usize,
+    span: Span,
     context: &'a str,
     filename: &'b str,
     kind: ErrorKind,
+    suggestion: Option<String>,
+    secondary_spans: Vec<(OwnedToken, String)>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Token<'a> {
     lexeme: &'a str,
+    span: Span,
+    kind: TokenKind<'a>,
+    synthetic: bool,
+    type_hint: Option<TypeHint>,
+    has_leading_space: bool,
+    has_trailing_space: bool,
+    taint: TaintLevel,
+    canonical_lexeme: Option<&'a str>,
+    right_associative: bool,
+    precedence: Option<u8>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub byte_offset: usize,
+    pub byte_length: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SemanticGroup<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    kind: SemanticGroupKind,
+}
+
+pub struct SiblingIter<'a> {
+    tokens: &'a [Token<'a>],
+    index: usize,
+    depth: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpanTable {
+    spans: Vec<(u32, u32, u32, u32)>,
+}
+
+#[derive(Debug)]
+pub struct TokenStream<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    source: &'a str,
+    filename: &'a str,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedToken {
+    lexeme: String,
+    offset: usize,
+    line: usize,
+    column: usize,
+    kind: OwnedTokenKind,
+    taint: TaintLevel,
+}
+
+#[derive(Clone, Debug)]
+pub struct InternedToken {
+    kind: OwnedTokenKind,
+    lexeme_id: u32,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct StringInterner {
+    ids: HashMap<String, u32>,
+    strings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct AstToken {
+    token: OwnedToken,
+    parent_id: Option<u64>,
+    child_ids: Vec<u64>,
+    depth: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    source_hash: u64,
+    config: Option<(TokenizerConfig, TaintLevel)>,
+    tokens: Vec<OwnedToken>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenRule {
+    kind: String,
+    followed_by: Vec<String>,
+}
+
+// Rules only constrain adjacent pairs: every rule for a kind must list the
+// kind that follows it. Longer, regex-like sequences are not expressible.
+#[derive(Clone, Debug, Default)]
+pub struct TokenSchema {
+    rules: Vec<TokenRule>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AliasTable(HashMap<String, String>);
+
+#[derive(Clone, Debug, Default)]
+pub struct TokenCounts {
+    pub total_tokens: usize,
+    pub unique_kinds: usize,
+    pub most_common_kind: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TokenizerMetrics {
+    pub total_bytes: u64,
+    pub total_tokens: u64,
+    pub total_errors: u64,
+    pub total_time_ns: u64,
+}
+
+#[derive(Debug)]
+pub struct OwnedError {
+    inner: Box<OwnedErrorInner>,
+}
+
+#[derive(Debug)]
+struct OwnedErrorInner {
+    lexeme: String,
+    span: Span,
+    context: String,
+    filename: String,
+    kind: ErrorKind,
+    suggestion: Option<String>,
+    secondary_spans: Vec<(OwnedToken, String)>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ScanIoError {
+    Io(io::Error),
+    Scan(OwnedError),
+    Compact(CompactError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactError {
+    FieldOverflow { field: &'static str, value: usize },
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    LexemeOutOfBounds { offset: usize, length: usize },
+    KindMismatch { kind: u8 },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseContext {
+    bracket_depth: usize,
+    last_was_operator: bool,
+}
+
+pub struct CommentMap<'t, 'a>(HashMap<u64, &'t Token<'a>>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenizerConfig {
+    pub doc_comments: bool,
+    pub regex_literals: bool,
+    pub allow_custom_ops: bool,
+    pub include_comments: bool,
+    pub type_hints: bool,
+    pub keywords: Vec<String>,
+    pub heredocs: bool,
+    pub preserve_whitespace: bool,
+    pub max_identifier_length: Option<usize>,
+    pub right_associative_ops: HashSet<String>,
+    pub operator_precedences: HashMap<String, u8>,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        TokenizerConfig {
+            doc_comments: false,
+            regex_literals: false,
+            allow_custom_ops: false,
+            include_comments: false,
+            type_hints: false,
+            keywords: Vec::new(),
+            heredocs: false,
+            preserve_whitespace: false,
+            max_identifier_length: None,
+            right_associative_ops: ["**", "=", "->", "=>"]
+                .iter()
+                .map(|op| (*op).to_owned())
+                .collect(),
+            operator_precedences: [
+                ("||", 3),
+                ("&&", 4),
+                ("==", 8),
+                ("!=", 8),
+                ("<=", 9),
+                (">=", 9),
+                ("+", 11),
+                ("-", 11),
+                ("*", 12),
+                ("/", 12),
+                ("%", 12),
+                ("**", 13),
+                ("!", 14),
+            ]
+            .iter()
+            .map(|(op, precedence)| ((*op).to_owned(), *precedence))
+            .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryAction {
+    SkipCharacter(char),
+    DiscardLexeme(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct RecoveryEntry {
+    position: usize,
+    line: usize,
+    column: usize,
+    action: RecoveryAction,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompletionContext {
+    prefix: String,
+    token_before_cursor: Option<OwnedToken>,
+    token_at_cursor: Option<OwnedToken>,
+    bracket_depth: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenizerState {
+    source_len: usize,
+    position: usize,
     line: usize,
     column: usize,
-    kind: TokenKind,
+    tokens_produced: usize,
+    last_token_kind: Option<String>,
 }
 
 #[allow(dead_code)]
 pub struct Tokenizer<'a, 'b> {
     source: &'a str,
     filename: &'b str,
+    config: TokenizerConfig,
+    taint: TaintLevel,
 
     tokens: Vec<Token<'a>>,
+    tokens_produced: usize,
+    last_token_kind: Option<&'static str>,
+    longest_lexeme: &'a str,
+    previous_lexeme: &'a str,
+    follows_value: bool,
 
     start: usize,
     current: usize,
@@ -53,7 +460,35 @@ impl Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let string = match self {
             ErrorKind::ExpectedDigit => "expected digit",
+            ErrorKind::IncompleteExponent => "incomplete exponent",
             ErrorKind::UnknownCharacter => "unknown character",
+            ErrorKind::UnreadableSource => "could not read source",
+            ErrorKind::UnterminatedRegex => "unterminated regex literal",
+            ErrorKind::UnterminatedString => "unterminated string literal",
+            ErrorKind::UnterminatedHeredoc => "unterminated heredoc literal",
+            ErrorKind::UnterminatedBlockComment => "unterminated block comment",
+            ErrorKind::AliasSubstituted { canonical } => {
+                return write!(f, "replaced alias with `{}`", canonical);
+            }
+            ErrorKind::ExpectedToken { expected, found } => {
+                return write!(f, "expected {}, found {}", expected, found);
+            }
+            ErrorKind::UnexpectedTokenSequence { after, found } => {
+                return write!(f, "unexpected {} after {}", found, after);
+            }
+            ErrorKind::NestingDepthExceeded { depth, max } => {
+                return write!(f, "nesting depth {} exceeds the maximum of {}", depth, max);
+            }
+            ErrorKind::IdentifierTooLong { length, max } => {
+                return write!(
+                    f,
+                    "identifier length {} exceeds the maximum of {}",
+                    length, max
+                );
+            }
+            ErrorKind::InsertedToken { lexeme } => {
+                return write!(f, "inserted missing `{}`", lexeme);
+            }
         };
 
         write!(f, "{}", string)
@@ -62,47 +497,64 @@ impl Display for ErrorKind {
 
 impl Display for Error<'_, '_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{}\n{} {}:{}:{}\n {}\n{} {} {}\n {}{}{}\n\n{}{}\n\n{}{}",
-            "error[E0001]".bright_red(),
-            ": ".bright_white(),
-            format!("{}", self.kind).bright_white(),
-            format!(
-                "{:>line_length$}",
-                "-->",
-                line_length = self.line.to_string().len() + 3
-            )
-            .bright_cyan(),
-            self.filename,
-            self.line,
-            self.column,
-            format!(
-                "{:>line_length$}",
-                "|",
-                line_length = self.line.to_string().len() + 1
-            )
-            .bright_cyan(),
-            self.line.to_string().bright_cyan(),
-            format!(
-                "{:>line_length$}",
-                "|",
-                line_length = self.line.to_string().len()
-            )
-            .bright_cyan(),
-            self.context,
-            format!(
-                "{:>line_length$}",
-                "|",
-                line_length = self.line.to_string().len() + 1
-            )
-            .bright_cyan(),
-            format!("{:>column$}", " ", column = self.column),
-            format!("{:lexeme_length$}", "^", lexeme_length = self.lexeme.len()).bright_red(),
-            "error".bright_red(),
-            ": aborting due to 1 previous error".bright_white(),
-            "error".bright_red(),
-            format!(": could not tokenize `{}`", self.filename).bright_white()
+        #[cfg(feature = "colors")]
+        let rendered = self.render(|text, style| match style {
+            Style::Error | Style::Caret => text.bright_red().to_string(),
+            Style::Message => text.bright_white().to_string(),
+            Style::Gutter => text.bright_cyan().to_string(),
+            Style::Secondary => text.bright_blue().to_string(),
+            Style::Plain => text.to_owned(),
+        });
+        #[cfg(not(feature = "colors"))]
+        let rendered = self.render(|text, _| text.to_owned());
+
+        write_diagnostic(f, rendered)
+    }
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnknownCharacter => "E0001",
+            ErrorKind::ExpectedDigit => "E0002",
+            ErrorKind::IncompleteExponent => "E0003",
+            ErrorKind::UnreadableSource => "E0004",
+            ErrorKind::UnterminatedRegex => "E0005",
+            ErrorKind::UnterminatedString => "E0007",
+            ErrorKind::UnterminatedHeredoc => "E0008",
+            ErrorKind::UnterminatedBlockComment => "E0009",
+            ErrorKind::NestingDepthExceeded { .. } => "E0010",
+            ErrorKind::UnexpectedTokenSequence { .. } => "E0011",
+            ErrorKind::AliasSubstituted { .. } => "E0012",
+            ErrorKind::IdentifierTooLong { .. } => "E0013",
+            ErrorKind::ExpectedToken { .. } => "E0014",
+            ErrorKind::InsertedToken { .. } => "E0006",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ErrorKind::ExpectedDigit => "expected_digit",
+            ErrorKind::IncompleteExponent => "incomplete_exponent",
+            ErrorKind::UnknownCharacter => "unknown_character",
+            ErrorKind::UnreadableSource => "unreadable_source",
+            ErrorKind::UnterminatedRegex => "unterminated_regex",
+            ErrorKind::UnterminatedString => "unterminated_string",
+            ErrorKind::UnterminatedHeredoc => "unterminated_heredoc",
+            ErrorKind::UnterminatedBlockComment => "unterminated_block_comment",
+            ErrorKind::NestingDepthExceeded { .. } => "nesting_depth_exceeded",
+            ErrorKind::IdentifierTooLong { .. } => "identifier_too_long",
+            ErrorKind::UnexpectedTokenSequence { .. } => "unexpected_token_sequence",
+            ErrorKind::ExpectedToken { .. } => "expected_token",
+            ErrorKind::AliasSubstituted { .. } => "alias_substituted",
+            ErrorKind::InsertedToken { .. } => "inserted_token",
+        }
+    }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::InsertedToken { .. } | ErrorKind::AliasSubstituted { .. }
         )
     }
 }
@@ -110,307 +562,3919 @@ impl Display for Error<'_, '_> {
 impl<'a, 'b> Error<'a, 'b> {
     pub fn new(
         lexeme: &'a str,
-        line: usize,
-        column: usize,
+        span: Span,
         context: &'a str,
         filename: &'b str,
         kind: ErrorKind,
     ) -> Error<'a, 'b> {
-        Error {
-            lexeme,
-            line,
-            column,
-            context,
-            filename,
-            kind,
-        }
+        Error::new_with_suggestion(lexeme, span, context, filename, kind, None)
     }
-}
 
-impl<'a, 'b> Tokenizer<'a, 'b> {
-    pub fn new(source: &'a str, filename: &'b str) -> Tokenizer<'a, 'b> {
-        Tokenizer {
-            source,
-            filename,
-            tokens: Vec::new(),
-            start: 0,
-            current: 0,
-            line: 1,
-            column: 1,
+    pub fn new_with_suggestion(
+        lexeme: &'a str,
+        span: Span,
+        context: &'a str,
+        filename: &'b str,
+        kind: ErrorKind,
+        suggestion: Option<String>,
+    ) -> Error<'a, 'b> {
+        Error {
+            inner: Box::new(ErrorInner {
+                lexeme,
+                span,
+                context,
+                filename,
+                kind,
+                suggestion,
+                secondary_spans: Vec::new(),
+            }),
         }
     }
 
-    pub fn scan_tokens(&'a mut self) -> Result<&'a [Token<'a>], Error<'a, 'b>> {
-        while !self.has_reached_eof() {
-            self.start = self.current;
-
-            self.scan_token()?
-        }
+    pub fn span(&self) -> Span {
+        self.inner.span
+    }
 
-        self.tokens.push(Token {
-            lexeme: "",
-            line: self.line,
-            column: 0,
-            kind: TokenKind::Eof,
-        });
+    pub fn annotate_secondary(mut self, token: OwnedToken, message: String) -> Self {
+        self.inner.secondary_spans.push((token, message));
+        self
+    }
 
-        Ok(&self.tokens)
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner.kind
     }
 
-    fn has_reached_eof(&self) -> bool {
-        self.current >= self.source.len()
+    pub fn message(&self) -> String {
+        self.inner.kind.to_string()
     }
 
-    fn scan_token(&mut self) -> Result<(), Error<'a, 'b>> {
-        let character = self.advance(1);
+    pub fn code(&self) -> &str {
+        self.inner.kind.code()
+    }
 
-        let result = match character.chars().next().unwrap() {
-            '(' | ')' => self.add_token(TokenKind::Bracket),
-            '{' => {
-                if self.match_next('-', false) {
-                    while !self.match_next_multiple("-}", false) {
-                        self.advance(1);
-                    }
-                } else {
-                    self.add_token(TokenKind::Bracket)
-                }
-            }
-            '}' => self.add_token(TokenKind::Bracket),
-            '<' | '>' => {
-                let kind = if self.match_next('=', false) {
-                    TokenKind::Operator
-                } else {
-                    TokenKind::Bracket
-                };
-                self.add_token(kind)
-            }
-            ',' | '.' | ';' => self.add_token(TokenKind::Punctuation),
-            '-' => {
-                if self.match_next('-', false) {
-                    self.read_while(|c| c.ne(&'\n'));
-                } else {
-                    self.add_token(TokenKind::Operator)
-                }
-            }
+    fn severity(&self) -> &'static str {
+        if self.inner.kind.is_warning() {
+            "warning"
+        } else {
+            "error"
+        }
+    }
 
-            '+' | '*' | '/' | '!' => self.add_token(TokenKind::Operator),
-            '=' => {
-                let kind = if self.match_next('=', false) {
-                    TokenKind::Operator
-                } else {
-                    TokenKind::Bracket
-                };
-                self.add_token(kind)
-            }
-            '0' => self.leading_zero_number()?,
-            '1'..='9' => self.number()?,
-            ' ' | '\t' => self.column += 1,
+    pub fn to_plain_string(&self) -> String {
+        let mut plain = String::new();
 
-            '\r' => {}
-            '\n' => {
-                self.column = 1;
-                self.line += 1;
-            }
-            _ => Err(self.boo(character, ErrorKind::UnknownCharacter))?,
-        };
+        write_diagnostic(&mut plain, self.render(|text, _| text.to_owned()))
+            .expect("writing to a String cannot fail");
 
-        Ok(result)
+        plain
     }
 
-    fn advance(&mut self, advance_by: usize) -> &'a str {
-        self.current += advance_by;
-
-        &self.source[self.current - advance_by..self.current]
+    pub fn render_plain(&self) -> String {
+        self.to_plain_string()
     }
 
-    fn peek(&self) -> char {
-        if self.has_reached_eof() {
-            return '\0';
-        }
-
-        self.source.as_bytes()[self.current] as char
+    pub fn format_json(&self) -> String {
+        format!(
+            "{{\"code\":\"{}\",\"kind\":\"{}\",\"line\":{},\"column\":{},\"lexeme\":\"{}\",\"filename\":\"{}\"}}",
+            self.code(),
+            self.inner.kind.name(),
+            self.inner.span.start_line,
+            self.inner.span.start_column,
+            escape_json(self.inner.lexeme),
+            escape_json(self.inner.filename)
+        )
     }
 
-    fn peek_str(&self, length: usize) -> &'a str {
-        if self.has_reached_eof() {
-            return "";
-        }
+    pub fn to_html(&self) -> String {
+        let [header, snippet, footer] = self.render(|text, style| {
+            let class = match style {
+                Style::Error => "error-code",
+                Style::Message => "error-message",
+                Style::Gutter => "error-gutter",
+                Style::Caret => "error-caret",
+                Style::Secondary => "error-secondary",
+                Style::Plain => return escape_html(text),
+            };
 
-        &self.source[self.current..self.current + length]
-    }
+            format!("<span class=\"{}\">{}</span>", class, escape_html(text))
+        });
 
-    fn match_next(&mut self, expected: char, lowercase: bool) -> bool {
-        if self.has_reached_eof() {
-            return false;
-        }
+        let mut html = format!(
+            "<div>{}</div>\n<pre>{}</pre>",
+            header.replace('\n', "<br>\n"),
+            snippet
+        );
 
-        let character = self.source.as_bytes()[self.current] as char;
-        if !lowercase && character != expected {
-            return false;
-        }
-        if lowercase
-            && character
-                .to_ascii_lowercase()
-                .ne(&expected.to_ascii_lowercase())
-        {
-            return false;
+        if !footer.is_empty() {
+            html.push_str(&format!("\n<div>{}</div>", footer.replace('\n', "<br>\n")));
         }
 
-        self.current += 1;
-        true
+        html
     }
 
-    fn match_next_predicate<P>(&mut self, predicate: P) -> bool
+    fn render<P>(&self, paint: P) -> [String; 3]
     where
-        P: Fn(char) -> bool,
+        P: Fn(&str, Style) -> String,
     {
-        if self.has_reached_eof() {
-            return false;
+        let line_length = self.inner.span.start_line.to_string().len();
+
+        let header = format!(
+            "{}{}\n{} {}",
+            paint(
+                &format!("{}[{}]", self.severity(), self.code()),
+                Style::Error
+            ),
+            paint(&format!(": {}", self.inner.kind), Style::Message),
+            paint(
+                &format!("{:>line_length$}", "-->", line_length = line_length + 3),
+                Style::Gutter
+            ),
+            paint(
+                &format!(
+                    "{}:{}:{}",
+                    self.inner.filename, self.inner.span.start_line, self.inner.span.start_column
+                ),
+                Style::Plain
+            ),
+        );
+
+        let mut snippet = format!(
+            " {}\n{} {} {}\n {}{}{}",
+            paint(
+                &format!("{:>line_length$}", "|", line_length = line_length + 1),
+                Style::Gutter
+            ),
+            paint(&self.inner.span.start_line.to_string(), Style::Gutter),
+            paint(
+                &format!("{:>line_length$}", "|", line_length = line_length),
+                Style::Gutter
+            ),
+            paint(self.inner.context, Style::Plain),
+            paint(
+                &format!("{:>line_length$}", "|", line_length = line_length + 1),
+                Style::Gutter
+            ),
+            " ".repeat(self.inner.span.start_column),
+            paint(
+                &format!(
+                    "{:lexeme_length$}",
+                    "^",
+                    lexeme_length = self.inner.lexeme.chars().count()
+                ),
+                Style::Caret
+            ),
+        );
+
+        for (token, message) in &self.inner.secondary_spans {
+            if token.line == self.inner.span.start_line {
+                snippet.push_str(&format!(
+                    "\n {}{}{}",
+                    paint(
+                        &format!("{:>line_length$}", "|", line_length = line_length + 1),
+                        Style::Gutter
+                    ),
+                    " ".repeat(token.column),
+                    paint(
+                        &format!("{} {}", "-".repeat(token.lexeme.len().max(1)), message),
+                        Style::Secondary
+                    ),
+                ));
+            } else {
+                snippet.push_str(&format!(
+                    "\n{} {}{}",
+                    paint(
+                        &format!("{:>line_length$}", "=", line_length = line_length + 2),
+                        Style::Gutter
+                    ),
+                    paint("note", Style::Secondary),
+                    paint(
+                        &format!(
+                            ": {} at {}:{}:{}",
+                            message, self.inner.filename, token.line, token.column
+                        ),
+                        Style::Secondary
+                    ),
+                ));
+            }
         }
 
-        let character = self.source.as_bytes()[self.current] as char;
-        if !predicate(character) {
-            return false;
+        if let Some(suggestion) = &self.inner.suggestion {
+            snippet.push_str(&format!(
+                "\n{} {}{}",
+                paint(
+                    &format!("{:>line_length$}", "=", line_length = line_length + 2),
+                    Style::Gutter
+                ),
+                paint("help", Style::Message),
+                paint(&format!(": did you mean `{}`?", suggestion), Style::Message),
+            ));
         }
 
-        self.current += 1;
-        true
-    }
+        if self.inner.kind.is_warning() {
+            return [header, snippet, String::new()];
+        }
 
-    fn match_next_multiple(&mut self, expected: &str, lowercase: bool) -> bool {
-        expected.chars().all(|c| self.match_next(c, lowercase))
+        let footer = format!(
+            "{}{}\n\n{}{}",
+            paint("error", Style::Error),
+            paint(": aborting due to 1 previous error", Style::Message),
+            paint("error", Style::Error),
+            paint(
+                &format!(": could not tokenize `{}`", self.inner.filename),
+                Style::Message
+            ),
+        );
+
+        [header, snippet, footer]
     }
+}
 
-    fn read_while<P>(&mut self, predicate: P)
-    where
-        P: Fn(char) -> bool,
-    {
-        while predicate(self.peek()) && !self.has_reached_eof() {
-            self.advance(1);
-        }
+impl Display for OwnedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&Error::from(self), f)
     }
+}
 
-    fn add_token(&mut self, kind: TokenKind) {
-        let lexeme = &self.source[self.start..self.current];
+impl std::error::Error for Error<'_, '_> {}
 
-        let token = Token {
-            lexeme,
-            line: self.line,
-            column: self.column,
-            kind,
-        };
+impl std::error::Error for OwnedError {}
 
-        self.column += lexeme.len();
-        self.tokens.push(token);
+impl Display for CompactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactError::FieldOverflow { field, value } => {
+                write!(f, "{} {} does not fit in a compact token", field, value)
+            }
+            CompactError::UnsupportedVersion(version) => {
+                write!(f, "unsupported compact token format version {}", version)
+            }
+            CompactError::UnknownKind(kind) => write!(f, "unknown compact token kind {}", kind),
+            CompactError::LexemeOutOfBounds { offset, length } => write!(
+                f,
+                "compact token lexeme at {}..{} is not a valid slice of the source",
+                offset,
+                offset + length
+            ),
+            CompactError::KindMismatch { kind } => {
+                write!(f, "compact token lexeme does not scan as kind {}", kind)
+            }
+        }
     }
+}
 
-    fn number(&mut self) -> Result<(), Error<'a, 'b>> {
-        self.read_while(|c| c.is_ascii_digit());
+impl std::error::Error for CompactError {}
 
-        let mut has_fractional_part = false;
+#[cfg(feature = "std")]
+impl Display for ScanIoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanIoError::Io(error) => write!(f, "could not write tokens: {}", error),
+            ScanIoError::Scan(error) => Display::fmt(error, f),
+            ScanIoError::Compact(error) => write!(f, "could not encode token: {}", error),
+        }
+    }
+}
 
-        if self.match_next('.', false) {
-            if self.match_next_predicate(|c| c.is_ascii_digit()) {
-                has_fractional_part = true;
-                self.read_while(|c| c.is_ascii_digit())
-            } else {
-                self.current -= 1;
-            }
+#[cfg(feature = "std")]
+impl std::error::Error for ScanIoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScanIoError::Io(error) => Some(error),
+            ScanIoError::Scan(error) => Some(error),
+            ScanIoError::Compact(error) => Some(error),
         }
-        if self.match_next('e', true) {
-            if self.match_next_predicate(|c| c.is_ascii_digit()) {
-                self.read_while(|c| c.is_ascii_digit())
-            } else if self.match_next('-', false)
-                && self.match_next_predicate(|c| c.is_ascii_digit())
-            {
-                self.read_while(|c| c.is_ascii_digit())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for ScanIoError {
+    fn from(error: io::Error) -> Self {
+        ScanIoError::Io(error)
+    }
+}
+
+impl Display for TokenizerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows = [
+            ("source_len", self.source_len.to_string()),
+            ("position", self.position.to_string()),
+            ("line", self.line.to_string()),
+            ("column", self.column.to_string()),
+            ("tokens_produced", self.tokens_produced.to_string()),
+            (
+                "last_token_kind",
+                self.last_token_kind
+                    .clone()
+                    .unwrap_or_else(|| String::from("-")),
+            ),
+        ];
+
+        for (name, value) in rows.iter() {
+            writeln!(f, "{:<15} | {}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a OwnedError> for Error<'a, 'a> {
+    fn from(error: &'a OwnedError) -> Self {
+        Error {
+            inner: Box::new(ErrorInner {
+                lexeme: &error.inner.lexeme,
+                span: error.inner.span,
+                context: &error.inner.context,
+                filename: &error.inner.filename,
+                kind: error.inner.kind.clone(),
+                suggestion: error.inner.suggestion.clone(),
+                secondary_spans: error.inner.secondary_spans.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&Error<'_, '_>> for OwnedError {
+    fn from(error: &Error<'_, '_>) -> Self {
+        OwnedError {
+            inner: Box::new(OwnedErrorInner {
+                lexeme: error.inner.lexeme.to_owned(),
+                span: error.inner.span,
+                context: error.inner.context.to_owned(),
+                filename: error.inner.filename.to_owned(),
+                kind: error.inner.kind.clone(),
+                suggestion: error.inner.suggestion.clone(),
+                secondary_spans: error.inner.secondary_spans.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&TokenKind<'_>> for OwnedTokenKind {
+    fn from(kind: &TokenKind<'_>) -> Self {
+        match kind {
+            TokenKind::Bracket(bracket) => OwnedTokenKind::Bracket((*bracket).to_owned()),
+            TokenKind::Punctuation(punctuation) => OwnedTokenKind::Punctuation(*punctuation),
+            TokenKind::Operator(op) => OwnedTokenKind::Operator((*op).to_owned()),
+            TokenKind::Eof => OwnedTokenKind::Eof,
+            TokenKind::DocComment => OwnedTokenKind::DocComment,
+            TokenKind::Whitespace => OwnedTokenKind::Whitespace,
+            TokenKind::Number(number) => OwnedTokenKind::Number(*number),
+            TokenKind::BigInt(bigint) => OwnedTokenKind::BigInt(bigint.clone()),
+            TokenKind::RegexLiteral(pattern) => OwnedTokenKind::RegexLiteral(pattern.clone()),
+            TokenKind::CustomOp(op) => OwnedTokenKind::CustomOp(op.clone()),
+            TokenKind::Shebang(shebang) => OwnedTokenKind::Shebang(shebang.clone()),
+            TokenKind::String(string) => OwnedTokenKind::String(string.clone()),
+            TokenKind::Identifier(name) => OwnedTokenKind::Identifier((*name).to_owned()),
+            TokenKind::Keyword(keyword) => OwnedTokenKind::Keyword((*keyword).to_owned()),
+            TokenKind::HeredocLiteral { tag, content } => OwnedTokenKind::HeredocLiteral {
+                tag: tag.clone(),
+                content: content.clone(),
+            },
+            TokenKind::Newline(count) => OwnedTokenKind::Newline(*count),
+            TokenKind::Spread => OwnedTokenKind::Spread,
+            TokenKind::Ellipsis => OwnedTokenKind::Ellipsis,
+            TokenKind::Boolean(value) => OwnedTokenKind::Boolean(*value),
+            TokenKind::Null => OwnedTokenKind::Null,
+        }
+    }
+}
+
+impl From<&Token<'_>> for OwnedToken {
+    fn from(token: &Token<'_>) -> Self {
+        OwnedToken {
+            lexeme: token.lexeme.to_owned(),
+            offset: token.span.byte_offset,
+            line: token.span.start_line,
+            column: token.span.start_column,
+            kind: OwnedTokenKind::from(&token.kind),
+            taint: token.taint,
+        }
+    }
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        OwnedToken::from(&token)
+    }
+}
+
+impl<'a> From<&'a OwnedTokenKind> for TokenKind<'a> {
+    fn from(kind: &'a OwnedTokenKind) -> Self {
+        match kind {
+            OwnedTokenKind::Bracket(bracket) => TokenKind::Bracket(bracket),
+            OwnedTokenKind::Punctuation(punctuation) => TokenKind::Punctuation(*punctuation),
+            OwnedTokenKind::Operator(op) => TokenKind::Operator(op),
+            OwnedTokenKind::Eof => TokenKind::Eof,
+            OwnedTokenKind::DocComment => TokenKind::DocComment,
+            OwnedTokenKind::Whitespace => TokenKind::Whitespace,
+            OwnedTokenKind::Number(number) => TokenKind::Number(*number),
+            OwnedTokenKind::BigInt(bigint) => TokenKind::BigInt(bigint.clone()),
+            OwnedTokenKind::RegexLiteral(pattern) => TokenKind::RegexLiteral(pattern.clone()),
+            OwnedTokenKind::CustomOp(op) => TokenKind::CustomOp(op.clone()),
+            OwnedTokenKind::Shebang(shebang) => TokenKind::Shebang(shebang.clone()),
+            OwnedTokenKind::String(string) => TokenKind::String(string.clone()),
+            OwnedTokenKind::Identifier(name) => TokenKind::Identifier(name),
+            OwnedTokenKind::Keyword(keyword) => TokenKind::Keyword(keyword),
+            OwnedTokenKind::HeredocLiteral { tag, content } => TokenKind::HeredocLiteral {
+                tag: tag.clone(),
+                content: content.clone(),
+            },
+            OwnedTokenKind::Newline(count) => TokenKind::Newline(*count),
+            OwnedTokenKind::Spread => TokenKind::Spread,
+            OwnedTokenKind::Ellipsis => TokenKind::Ellipsis,
+            OwnedTokenKind::Boolean(value) => TokenKind::Boolean(*value),
+            OwnedTokenKind::Null => TokenKind::Null,
+        }
+    }
+}
+
+impl<'a> From<&'a OwnedToken> for Token<'a> {
+    fn from(token: &'a OwnedToken) -> Self {
+        Token {
+            lexeme: &token.lexeme,
+            span: Span::new(&token.lexeme, token.offset, token.line, token.column),
+            kind: TokenKind::from(&token.kind),
+            synthetic: false,
+            type_hint: None,
+            has_leading_space: false,
+            has_trailing_space: false,
+            taint: token.taint,
+            canonical_lexeme: None,
+            right_associative: false,
+            precedence: None,
+        }
+    }
+}
+
+impl<'a> TokenKind<'a> {
+    fn compact_discriminant(&self) -> u8 {
+        match self {
+            TokenKind::Bracket(_) => 0,
+            TokenKind::Punctuation(_) => 1,
+            TokenKind::Operator(_) => 2,
+            TokenKind::Eof => 3,
+            TokenKind::Number(_) => 4,
+            TokenKind::BigInt(_) => 5,
+            TokenKind::DocComment => 6,
+            TokenKind::RegexLiteral(_) => 7,
+            TokenKind::Whitespace => 8,
+            TokenKind::CustomOp(_) => 9,
+            TokenKind::Shebang(_) => 10,
+            TokenKind::String(_) => 11,
+            TokenKind::Identifier(_) => 12,
+            TokenKind::Keyword(_) => 13,
+            TokenKind::HeredocLiteral { .. } => 14,
+            TokenKind::Newline(_) => 15,
+            TokenKind::Spread => 16,
+            TokenKind::Ellipsis => 17,
+            TokenKind::Boolean(_) => 18,
+            TokenKind::Null => 19,
+        }
+    }
+
+    fn from_compact(discriminant: u8, lexeme: &'a str) -> Result<TokenKind<'a>, CompactError> {
+        let rescanned = || {
+            let config = TokenizerConfig {
+                regex_literals: true,
+                heredocs: true,
+                ..TokenizerConfig::default()
+            };
+
+            match Tokenizer::with_config(lexeme, "", config).next_token() {
+                Ok(token) if token.kind.compact_discriminant() == discriminant => Ok(token.kind),
+                _ => Err(CompactError::KindMismatch { kind: discriminant }),
+            }
+        };
+
+        let kind = match discriminant {
+            0 => TokenKind::Bracket(lexeme),
+            1 => TokenKind::Punctuation(lexeme.chars().next().unwrap_or('\0')),
+            2 => TokenKind::Operator(lexeme),
+            3 => TokenKind::Eof,
+            4 | 5 | 7 | 11 | 14 => rescanned()?,
+            6 => TokenKind::DocComment,
+            8 => TokenKind::Whitespace,
+            9 => TokenKind::CustomOp(lexeme.to_owned()),
+            10 => TokenKind::Shebang(lexeme.to_owned()),
+            12 => TokenKind::Identifier(lexeme),
+            13 => TokenKind::Keyword(lexeme),
+            15 => TokenKind::Newline(lexeme.matches('\n').count()),
+            16 => TokenKind::Spread,
+            17 => TokenKind::Ellipsis,
+            18 => TokenKind::Boolean(lexeme == "true"),
+            19 => TokenKind::Null,
+            _ => return Err(CompactError::UnknownKind(discriminant)),
+        };
+
+        Ok(kind)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            TokenKind::Bracket(lexeme)
+            | TokenKind::Operator(lexeme)
+            | TokenKind::Keyword(lexeme) => {
+                format!("`{}`", lexeme)
             }
+            TokenKind::Punctuation(punctuation) => format!("`{}`", punctuation),
+            kind => kind.name().to_owned(),
         }
+    }
 
-        let lexeme = &self.source[self.start..self.current];
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Bracket(_) => "bracket",
+            TokenKind::Punctuation(_) => "punctuation",
+            TokenKind::Operator(_) => "operator",
+            TokenKind::Eof => "eof",
+            TokenKind::DocComment => "doc_comment",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Number(_) => "number",
+            TokenKind::BigInt(_) => "bigint",
+            TokenKind::RegexLiteral(_) => "regex",
+            TokenKind::CustomOp(_) => "custom_op",
+            TokenKind::Shebang(_) => "shebang",
+            TokenKind::String(_) => "string",
+            TokenKind::Identifier(_) => "identifier",
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::HeredocLiteral { .. } => "heredoc",
+            TokenKind::Newline(_) => "newline",
+            TokenKind::Spread => "spread",
+            TokenKind::Ellipsis => "ellipsis",
+            TokenKind::Boolean(_) => "boolean",
+            TokenKind::Null => "null",
+        }
+    }
+}
 
-        let bigint = if has_fractional_part {
-            false
-        } else {
-            self.match_next('n', false)
+impl Span {
+    pub fn new(lexeme: &str, byte_offset: usize, line: usize, column: usize) -> Span {
+        let (end_line, end_column) = match lexeme.rfind('\n') {
+            Some(last_newline) => (
+                line + lexeme.matches('\n').count(),
+                lexeme[last_newline..].chars().count(),
+            ),
+            None => (line, column + lexeme.chars().count()),
         };
-        if bigint {
-            let literal = lexeme.parse::<BigInt>().unwrap();
 
-            self.add_token(TokenKind::BigInt(literal));
+        Span {
+            start_line: line,
+            start_column: column,
+            end_line,
+            end_column,
+            byte_offset,
+            byte_length: lexeme.len(),
+        }
+    }
+}
 
-            return Ok(());
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.start_line, self.start_column, self.end_line, self.end_column
+        )
+    }
+}
+
+impl Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} @ {}..{}",
+            self,
+            self.byte_offset,
+            self.byte_offset + self.byte_length
+        )
+    }
+}
+
+impl<'a> Token<'a> {
+    // Compact format, version 1, little-endian:
+    // [0] kind, [1..3] lexeme length, [3..7] start offset, [7..10] line,
+    // [10..13] column, [13] format version, [14..16] reserved.
+    // Tokens with a value too large for its field cannot be encoded.
+    pub fn to_compact_bytes(&self) -> Result<[u8; 16], CompactError> {
+        let mut bytes = [0; 16];
+
+        let fields = [
+            ("lexeme length", self.lexeme.len(), 1..3),
+            ("offset", self.span.byte_offset, 3..7),
+            ("line", self.span.start_line, 7..10),
+            ("column", self.span.start_column, 10..13),
+        ];
+        for (field, value, range) in fields {
+            if value >> (8 * range.len()) != 0 {
+                return Err(CompactError::FieldOverflow { field, value });
+            }
+
+            bytes[range.clone()].copy_from_slice(&(value as u32).to_le_bytes()[..range.len()]);
         }
 
-        let literal = lexeme.parse::<f64>().unwrap();
+        bytes[0] = self.kind.compact_discriminant();
+        bytes[13] = COMPACT_FORMAT_VERSION;
 
-        self.add_token(TokenKind::Number(literal));
+        Ok(bytes)
+    }
 
-        Ok(())
+    // Empty lexemes (such as Eof) yield an empty range.
+    pub fn to_range_inclusive(&self) -> RangeInclusive<usize> {
+        match self.lexeme.len() {
+            0 => RangeInclusive::new(self.span.byte_offset + 1, self.span.byte_offset),
+            len => self.span.byte_offset..=self.span.byte_offset + len - 1,
+        }
     }
 
-    fn leading_zero_number(&mut self) -> Result<(), Error<'a, 'b>> {
-        if self.match_next('b', true) {
-            if self.match_next_predicate(|c| c.is_digit(2)) {
-                self.read_while(|c| c.is_digit(2))
-            } else {
-                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+    pub fn from_range_inclusive(
+        source: &'a str,
+        start: usize,
+        end: usize,
+        line: usize,
+        column: usize,
+        kind: TokenKind<'a>,
+    ) -> Token<'a> {
+        let (has_leading_space, has_trailing_space) = surrounding_space(source, start, end + 1);
+
+        Token {
+            lexeme: &source[start..=end],
+            span: Span::new(&source[start..=end], start, line, column),
+            kind,
+            synthetic: false,
+            type_hint: None,
+            has_leading_space,
+            has_trailing_space,
+            taint: TaintLevel::Trusted,
+            canonical_lexeme: None,
+            right_associative: false,
+            precedence: None,
+        }
+    }
+
+    pub fn to_ir_string(&self) -> String {
+        format!(
+            "{}({})@{}:{}",
+            self.kind.name().to_ascii_uppercase(),
+            self.lexeme,
+            self.span.start_line,
+            self.span.start_column
+        )
+    }
+
+    pub fn lexeme(&self) -> &'a str {
+        self.lexeme
+    }
+
+    pub fn kind(&self) -> &TokenKind<'a> {
+        &self.kind
+    }
+
+    pub fn line(&self) -> usize {
+        self.span.start_line
+    }
+
+    pub fn column(&self) -> usize {
+        self.span.start_column
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn is_adjacent_to_next(&self, next: &Token) -> bool {
+        self.span.byte_offset + self.span.byte_length == next.span.byte_offset
+    }
+
+    pub fn is_synthetic(&self) -> bool {
+        self.synthetic
+    }
+
+    pub fn type_hint(&self) -> Option<TypeHint> {
+        self.type_hint
+    }
+
+    pub fn has_leading_space(&self) -> bool {
+        self.has_leading_space
+    }
+
+    pub fn has_trailing_space(&self) -> bool {
+        self.has_trailing_space
+    }
+
+    pub fn original_lexeme(&self) -> Option<&'a str> {
+        self.canonical_lexeme.map(|_| self.lexeme)
+    }
+
+    pub fn canonical_lexeme(&self) -> &'a str {
+        self.canonical_lexeme.unwrap_or(self.lexeme)
+    }
+
+    pub fn is_right_associative(&self) -> bool {
+        self.right_associative
+    }
+
+    pub fn operator_precedence(&self) -> Option<u8> {
+        self.precedence
+    }
+
+    pub fn taint(&self) -> TaintLevel {
+        self.taint
+    }
+
+    pub fn to_sexpr(&self) -> String {
+        format!(
+            "({} {:?} {} {})",
+            self.kind.name(),
+            self.lexeme,
+            self.span.start_line,
+            self.span.start_column
+        )
+    }
+
+    pub fn to_tree_sitter_node_type(&self) -> &'static str {
+        match self.kind {
+            TokenKind::Bracket(_) => "bracket",
+            TokenKind::Punctuation(_) => "punctuation",
+            TokenKind::Operator(_) => "operator",
+            TokenKind::Eof => "end",
+            TokenKind::DocComment => "comment",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Number(_) => "number_literal",
+            TokenKind::BigInt(_) => "bigint_literal",
+            TokenKind::RegexLiteral(_) => "regex_literal",
+            TokenKind::CustomOp(_) => "operator",
+            TokenKind::Shebang(_) => "comment",
+            TokenKind::String(_) => "string_literal",
+            TokenKind::Identifier(_) => "identifier",
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::HeredocLiteral { .. } => "heredoc_literal",
+            TokenKind::Newline(_) => "newline",
+            TokenKind::Spread => "spread",
+            TokenKind::Ellipsis => "ellipsis",
+            TokenKind::Boolean(_) => "boolean",
+            TokenKind::Null => "null",
+        }
+    }
+
+    pub fn is_contextually_complete(tokens: &[Token], context: &mut ParseContext) -> bool {
+        for token in tokens {
+            if let TokenKind::Eof
+            | TokenKind::DocComment
+            | TokenKind::Whitespace
+            | TokenKind::Newline(_)
+            | TokenKind::Shebang(_) = token.kind
+            {
+                continue;
             }
-        } else if self.match_next('o', true) {
-            if self.match_next_predicate(|c| c.is_digit(8)) {
-                self.read_while(|c| c.is_digit(8))
-            } else {
-                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+
+            if token.opens_bracket() {
+                context.bracket_depth += 1;
+            } else if token.closes_bracket() {
+                context.bracket_depth = context.bracket_depth.saturating_sub(1);
             }
-        } else if self.match_next('x', false) {
-            if self.match_next_predicate(|c| c.is_digit(16)) {
-                self.read_while(|c| c.is_digit(16))
-            } else {
-                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+
+            context.last_was_operator =
+                matches!(token.kind, TokenKind::Operator(_) | TokenKind::CustomOp(_));
+        }
+
+        context.bracket_depth == 0 && !context.last_was_operator
+    }
+
+    pub fn classify_number(&self) -> Option<NumberClass> {
+        match self.kind {
+            TokenKind::BigInt(_) => Some(NumberClass::BigInt),
+            TokenKind::Number(_) => {
+                let lexeme = self.lexeme.to_ascii_lowercase();
+
+                let class = if lexeme.starts_with("0x") {
+                    NumberClass::Hex
+                } else if lexeme.starts_with("0o") {
+                    NumberClass::Octal
+                } else if lexeme.starts_with("0b") {
+                    NumberClass::Binary
+                } else if lexeme.contains(['.', 'e']) {
+                    NumberClass::Float
+                } else {
+                    NumberClass::Integer
+                };
+
+                Some(class)
             }
-        } else {
-            return self.number();
+            _ => None,
         }
+    }
 
-        let lexeme = &self.source[self.start..self.current];
+    pub fn is_value_token(&self) -> bool {
+        match self.kind {
+            TokenKind::Number(_)
+            | TokenKind::BigInt(_)
+            | TokenKind::String(_)
+            | TokenKind::HeredocLiteral { .. }
+            | TokenKind::RegexLiteral(_)
+            | TokenKind::Boolean(_)
+            | TokenKind::Null
+            | TokenKind::Identifier(_) => true,
+            TokenKind::Bracket(bracket) => matches!(bracket, "(" | "[" | "{"),
+            TokenKind::Operator(op) => matches!(op, "-" | "+" | "!"),
+            _ => false,
+        }
+    }
 
-        let second_char = lexeme.to_ascii_lowercase().chars().nth(1).unwrap();
+    pub fn classify_punctuation(&self) -> Option<PunctuationKind> {
+        match self.kind {
+            TokenKind::Punctuation(',') => Some(PunctuationKind::Comma),
+            TokenKind::Punctuation('.') => Some(PunctuationKind::Dot),
+            TokenKind::Punctuation(';') => Some(PunctuationKind::Semicolon),
+            _ => None,
+        }
+    }
 
-        let bigint = self.match_next('n', false);
-        if bigint {
-            let literal = match second_char {
-                'b' => u64::from_str_radix(&lexeme[2..], 2),
-                'o' => u64::from_str_radix(&lexeme[2..], 8),
-                _ => u64::from_str_radix(&lexeme[2..], 16),
+    pub fn is_comma(&self) -> bool {
+        self.classify_punctuation() == Some(PunctuationKind::Comma)
+    }
+
+    pub fn is_dot(&self) -> bool {
+        self.classify_punctuation() == Some(PunctuationKind::Dot)
+    }
+
+    pub fn is_semicolon(&self) -> bool {
+        self.classify_punctuation() == Some(PunctuationKind::Semicolon)
+    }
+
+    pub fn group_by_line(tokens: &'a [Token<'a>]) -> Vec<&'a [Token<'a>]> {
+        tokens
+            .chunk_by(|a, b| a.span.start_line == b.span.start_line)
+            .collect()
+    }
+
+    pub fn expand_whitespace(tokens: &'a [Token<'a>], source: &'a str) -> Vec<Token<'a>> {
+        let mut expanded = Vec::with_capacity(tokens.len() * 2);
+
+        let mut end = 0;
+        let mut line = 1;
+        let mut column = 1;
+
+        for token in tokens {
+            if token.span.byte_offset > end {
+                expanded.push(Token {
+                    lexeme: &source[end..token.span.byte_offset],
+                    span: Span::new(&source[end..token.span.byte_offset], end, line, column),
+                    kind: TokenKind::Whitespace,
+                    synthetic: false,
+                    type_hint: None,
+                    has_leading_space: false,
+                    has_trailing_space: false,
+                    taint: token.taint,
+                    canonical_lexeme: None,
+                    right_associative: false,
+                    precedence: None,
+                });
             }
-            .unwrap()
-            .to_bigint()
-            .unwrap();
 
-            self.add_token(TokenKind::BigInt(literal));
+            expanded.push(token.clone());
 
-            return Ok(());
+            end = token.span.byte_offset + token.lexeme.len();
+            line = token.span.end_line;
+            column = token.span.end_column;
         }
 
-        let literal = match second_char {
-            'b' => u64::from_str_radix(&lexeme[2..], 2),
-            'o' => u64::from_str_radix(&lexeme[2..], 8),
-            _ => u64::from_str_radix(&lexeme[2..], 16),
+        expanded
+    }
+
+    pub fn siblings(tokens: &'a [Token<'a>], from: usize) -> SiblingIter<'a> {
+        SiblingIter {
+            tokens,
+            index: from,
+            depth: 0,
         }
-        .unwrap() as f64;
+    }
 
-        self.add_token(TokenKind::Number(literal));
+    pub fn to_tree_cursor_event(&self) -> TreeCursorEvent<'_> {
+        let bracket = match self.lexeme {
+            "(" | ")" => BracketKind::Paren,
+            _ => BracketKind::Brace,
+        };
 
-        Ok(())
+        if self.opens_bracket() {
+            TreeCursorEvent::Open(bracket)
+        } else if self.closes_bracket() {
+            TreeCursorEvent::Close(bracket)
+        } else {
+            TreeCursorEvent::Leaf(self)
+        }
     }
 
-    fn boo(&self, lexeme: &'a str, kind: ErrorKind) -> Error<'a, 'b> {
-        let line = self.source.lines().nth(self.line - 1).unwrap();
+    fn opens_bracket(&self) -> bool {
+        matches!(self.kind, TokenKind::Bracket("(" | "{"))
+    }
 
-        Error::new(lexeme, self.line, self.column, line, self.filename, kind)
+    fn closes_bracket(&self) -> bool {
+        matches!(self.kind, TokenKind::Bracket(")" | "}"))
     }
 }
 
-pub fn run<'a>(tokenizer: &'a mut Tokenizer<'a, 'a>) -> Result<&'a [Token<'a>], Error<'a, 'a>> {
-    tokenizer.scan_tokens()
+impl<'t, 'a> SemanticGroup<'t, 'a> {
+    fn new(tokens: &'t [Token<'a>]) -> SemanticGroup<'t, 'a> {
+        let kind = match tokens.first() {
+            Some(Token {
+                kind: TokenKind::Keyword(_),
+                ..
+            }) => SemanticGroupKind::Declaration,
+            _ => SemanticGroupKind::Statement,
+        };
+
+        SemanticGroup { tokens, kind }
+    }
+
+    pub fn tokens(&self) -> &'t [Token<'a>] {
+        self.tokens
+    }
+
+    pub fn kind(&self) -> SemanticGroupKind {
+        self.kind
+    }
+}
+
+impl<'a> Iterator for SiblingIter<'a> {
+    type Item = &'a Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(token) = self.tokens.get(self.index) {
+            self.index += 1;
+
+            if token.closes_bracket() {
+                if self.depth == 0 {
+                    self.index = self.tokens.len();
+
+                    return None;
+                }
+
+                self.depth -= 1;
+            }
+
+            let sibling = self.depth == 0;
+
+            if token.opens_bracket() {
+                self.depth += 1;
+            }
+
+            if sibling {
+                return Some(token);
+            }
+        }
+
+        None
+    }
+}
+
+impl SpanTable {
+    pub fn get(&self, idx: usize) -> Option<(u32, u32, u32, u32)> {
+        self.spans.get(idx).copied()
+    }
+
+    pub fn range_overlapping(&self, start: u32, end: u32) -> Vec<usize> {
+        self.spans
+            .iter()
+            .enumerate()
+            .filter(|(_, &(span_start, span_end, _, _))| span_start < end && start < span_end)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+impl<'a> From<&[Token<'a>]> for SpanTable {
+    fn from(tokens: &[Token<'a>]) -> Self {
+        let spans = tokens
+            .iter()
+            .map(|token| {
+                (
+                    token.span.byte_offset as u32,
+                    (token.span.byte_offset + token.lexeme.len()) as u32,
+                    token.span.start_line as u32,
+                    token.span.start_column as u32,
+                )
+            })
+            .collect();
+
+        SpanTable { spans }
+    }
+}
+
+impl<'a> TokenStream<'a> {
+    // `source` and `filename` are what `expect` quotes in its diagnostics,
+    // so they must be the ones the tokens were scanned from.
+    pub fn new(tokens: Vec<Token<'a>>, source: &'a str, filename: &'a str) -> TokenStream<'a> {
+        TokenStream {
+            tokens,
+            position: 0,
+            source,
+            filename,
+        }
+    }
+
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    pub fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn advance(&mut self) -> Option<&Token<'a>> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+
+        Some(token)
+    }
+
+    pub fn expect(&mut self, kind: TokenKind<'a>) -> Result<&Token<'a>, Error<'a, 'a>> {
+        match self.tokens.get(self.position) {
+            Some(token) if token.kind == kind => {
+                self.position += 1;
+
+                Ok(token)
+            }
+            found => {
+                let (lexeme, span, found) = match found {
+                    Some(token) => (token.lexeme, token.span, token.kind.describe()),
+                    None => {
+                        let end = self.tokens.last().map_or(Span::new("", 0, 1, 1), |token| {
+                            Span::new(
+                                "",
+                                token.span.byte_offset + token.span.byte_length,
+                                token.span.end_line,
+                                token.span.end_column,
+                            )
+                        });
+
+                        ("", end, TokenKind::Eof.describe())
+                    }
+                };
+                let context = self.source.lines().nth(span.start_line - 1).unwrap_or("");
+
+                Err(Error::new(
+                    lexeme,
+                    span,
+                    context,
+                    self.filename,
+                    ErrorKind::ExpectedToken {
+                        expected: kind.describe(),
+                        found,
+                    },
+                ))
+            }
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        !matches!(self.peek(), Some(token) if !matches!(token.kind, TokenKind::Eof))
+    }
+
+    // Line and column of the next token, or of the last one once exhausted.
+    pub fn current_position(&self) -> (usize, usize) {
+        self.peek()
+            .or_else(|| self.tokens.last())
+            .map_or((1, 1), |token| {
+                (token.span.start_line, token.span.start_column)
+            })
+    }
+
+    pub fn to_sexpr(&self) -> String {
+        let mut sexpr = String::from("(tokens");
+
+        for token in &self.tokens {
+            sexpr.push(' ');
+            sexpr.push_str(&token.to_sexpr());
+        }
+
+        sexpr.push(')');
+        sexpr
+    }
+
+    pub fn to_source_text(&self, source: &str) -> String {
+        let mut text = String::with_capacity(source.len());
+
+        let mut end = 0;
+        for token in &self.tokens {
+            if token.span.byte_offset > end {
+                text.push_str(&source[end..token.span.byte_offset]);
+            }
+
+            text.push_str(token.lexeme);
+            end = end.max(token.span.byte_offset + token.lexeme.len());
+        }
+
+        text
+    }
+
+    pub fn to_tree_cursor_events(&self) -> Vec<TreeCursorEvent<'_>> {
+        self.tokens
+            .iter()
+            .map(Token::to_tree_cursor_event)
+            .collect()
+    }
+
+    pub fn token_pairs_matching<F>(&self, predicate: F) -> Vec<(usize, usize)>
+    where
+        F: Fn(&Token, &Token) -> bool,
+    {
+        let mut pairs = Vec::new();
+
+        for (i, a) in self.tokens.iter().enumerate() {
+            for (j, b) in self.tokens.iter().enumerate().skip(i + 1) {
+                if predicate(a, b) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+
+        pairs
+    }
+
+    // Unbalanced closers are skipped; pairs are ordered by opener.
+    pub fn matching_brackets(&self) -> Vec<(usize, usize)> {
+        let mut open = Vec::new();
+        let mut pairs = Vec::new();
+
+        for (index, token) in self.tokens.iter().enumerate() {
+            match token.to_tree_cursor_event() {
+                TreeCursorEvent::Open(kind) => open.push((index, kind)),
+                TreeCursorEvent::Close(kind) => {
+                    if let Some(&(opener, _)) = open.last().filter(|(_, open)| *open == kind) {
+                        open.pop();
+                        pairs.push((opener, index));
+                    }
+                }
+                TreeCursorEvent::Leaf(_) => {}
+            }
+        }
+
+        pairs.sort_unstable();
+        pairs
+    }
+
+    pub fn find_all_matching<F>(&self, predicate: F) -> Vec<usize>
+    where
+        F: Fn(&Token) -> bool,
+    {
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| predicate(token))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn find_first_matching<F>(&self, predicate: F) -> Option<usize>
+    where
+        F: Fn(&Token) -> bool,
+    {
+        self.tokens.iter().position(predicate)
+    }
+
+    pub fn find_last_matching<F>(&self, predicate: F) -> Option<usize>
+    where
+        F: Fn(&Token) -> bool,
+    {
+        self.tokens.iter().rposition(predicate)
+    }
+}
+
+impl OwnedToken {
+    pub fn taint(&self) -> TaintLevel {
+        self.taint
+    }
+
+    pub fn from_compact_bytes(bytes: [u8; 16], source: &str) -> Result<OwnedToken, CompactError> {
+        if bytes[13] != COMPACT_FORMAT_VERSION {
+            return Err(CompactError::UnsupportedVersion(bytes[13]));
+        }
+
+        let read = |range: std::ops::Range<usize>| {
+            let mut word = [0; 4];
+            word[..range.len()].copy_from_slice(&bytes[range]);
+
+            u32::from_le_bytes(word) as usize
+        };
+
+        let length = read(1..3);
+        let offset = read(3..7);
+        let lexeme = source
+            .get(offset..offset + length)
+            .ok_or(CompactError::LexemeOutOfBounds { offset, length })?;
+        let kind = TokenKind::from_compact(bytes[0], lexeme)?;
+
+        Ok(OwnedToken {
+            lexeme: lexeme.to_owned(),
+            offset,
+            line: read(7..10),
+            column: read(10..13),
+            kind: OwnedTokenKind::from(&kind),
+            taint: TaintLevel::Untrusted,
+        })
+    }
+}
+
+impl InternedToken {
+    pub fn kind(&self) -> &OwnedTokenKind {
+        &self.kind
+    }
+
+    pub fn lexeme_id(&self) -> u32 {
+        self.lexeme_id
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl StringInterner {
+    pub fn new() -> StringInterner {
+        StringInterner::default()
+    }
+
+    pub fn get_or_intern(&mut self, string: &str) -> u32 {
+        if let Some(&id) = self.ids.get(string) {
+            return id;
+        }
+
+        let id = self.strings.len() as u32;
+
+        self.strings.push(string.to_owned());
+        self.ids.insert(string.to_owned(), id);
+
+        id
+    }
+
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.strings.get(id as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+impl AstToken {
+    pub fn token(&self) -> &OwnedToken {
+        &self.token
+    }
+
+    pub fn parent_id(&self) -> Option<u64> {
+        self.parent_id
+    }
+
+    pub fn child_ids(&self) -> &[u64] {
+        &self.child_ids
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+}
+
+impl RecoveryEntry {
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn action(&self) -> RecoveryAction {
+        self.action
+    }
+}
+
+impl TokenCache {
+    pub fn new() -> TokenCache {
+        TokenCache::default()
+    }
+
+    pub fn tokens(&self) -> &[OwnedToken] {
+        &self.tokens
+    }
+}
+
+impl AliasTable {
+    pub fn new() -> AliasTable {
+        AliasTable::default()
+    }
+
+    pub fn insert(&mut self, alias: &str, canonical: &str) -> Option<String> {
+        self.0.insert(alias.to_owned(), canonical.to_owned())
+    }
+
+    pub fn get(&self, alias: &str) -> Option<&str> {
+        self.0.get(alias).map(String::as_str)
+    }
+
+    fn longest_match(&self, rest: &str) -> Option<(&str, &str)> {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+        self.0
+            .iter()
+            .filter(|(alias, _)| !alias.is_empty())
+            .filter(|(alias, _)| match rest.strip_prefix(alias.as_str()) {
+                Some(after) => !(alias.ends_with(is_word) && after.starts_with(is_word)),
+                None => false,
+            })
+            .max_by_key(|(alias, _)| alias.len())
+            .map(|(alias, canonical)| (alias.as_str(), canonical.as_str()))
+    }
+}
+
+impl From<HashMap<String, String>> for AliasTable {
+    fn from(aliases: HashMap<String, String>) -> Self {
+        AliasTable(aliases)
+    }
+}
+
+impl CompletionContext {
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn token_before_cursor(&self) -> Option<&OwnedToken> {
+        self.token_before_cursor.as_ref()
+    }
+
+    pub fn token_at_cursor(&self) -> Option<&OwnedToken> {
+        self.token_at_cursor.as_ref()
+    }
+
+    pub fn bracket_depth(&self) -> usize {
+        self.bracket_depth
+    }
+}
+
+impl TokenSchema {
+    pub fn new() -> TokenSchema {
+        TokenSchema::default()
+    }
+
+    pub fn rule(mut self, kind: &str, followed_by: &[&str]) -> Self {
+        self.rules.push(TokenRule {
+            kind: kind.to_owned(),
+            followed_by: followed_by.iter().map(|&name| name.to_owned()).collect(),
+        });
+        self
+    }
+
+    pub fn rules(&self) -> &[TokenRule] {
+        &self.rules
+    }
+
+    fn allows(&self, kind: &str, next: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| rule.kind == kind)
+            .all(|rule| rule.followed_by.iter().any(|allowed| allowed == next))
+    }
+}
+
+impl TokenRule {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn followed_by(&self) -> &[String] {
+        &self.followed_by
+    }
+}
+
+impl ParseContext {
+    pub fn bracket_depth(&self) -> usize {
+        self.bracket_depth
+    }
+
+    pub fn last_was_operator(&self) -> bool {
+        self.last_was_operator
+    }
+}
+
+impl<'t, 'a> CommentMap<'t, 'a> {
+    pub fn get(&self, id: u64) -> Option<&'t Token<'a>> {
+        self.0.get(&id).copied()
+    }
+}
+
+impl<'a, 'b> Tokenizer<'a, 'b> {
+    pub fn new(source: &'a str, filename: &'b str) -> Tokenizer<'a, 'b> {
+        Tokenizer::with_config(source, filename, TokenizerConfig::default())
+    }
+
+    pub fn with_config(
+        source: &'a str,
+        filename: &'b str,
+        config: TokenizerConfig,
+    ) -> Tokenizer<'a, 'b> {
+        Tokenizer {
+            source,
+            filename,
+            config,
+            taint: TaintLevel::Trusted,
+            tokens: Vec::new(),
+            tokens_produced: 0,
+            last_token_kind: None,
+            longest_lexeme: "",
+            previous_lexeme: "",
+            follows_value: false,
+            start: 0,
+            current: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn dump_state(&self) -> TokenizerState {
+        TokenizerState {
+            source_len: self.source.len(),
+            position: self.current,
+            line: self.line,
+            column: self.column,
+            tokens_produced: self.tokens_produced,
+            last_token_kind: self.last_token_kind.map(str::to_owned),
+        }
+    }
+
+    pub fn scan_tokens_for_completion(&self, cursor: usize) -> CompletionContext {
+        let mut cursor = cursor.min(self.source.len());
+        while !self.source.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+
+        let source = &self.source[..cursor];
+        let mut tokens: Vec<Token> = Vec::new();
+
+        for result in Tokenizer::with_config(source, self.filename, self.config.clone()) {
+            let error = match result {
+                Ok(token) => {
+                    tokens.push(token);
+                    continue;
+                }
+                Err(error) => error,
+            };
+
+            // A literal the user is still typing fails as unterminated; keep
+            // what has been typed so far as the token under the cursor.
+            let span = error.span();
+            if span.byte_offset + span.byte_length != cursor || span.byte_length == 0 {
+                continue;
+            }
+
+            let lexeme = &source[span.byte_offset..cursor];
+            let kind = match error.kind() {
+                ErrorKind::UnterminatedString => TokenKind::String(unescape_string(&lexeme[1..])),
+                ErrorKind::UnterminatedRegex => TokenKind::RegexLiteral(lexeme[1..].to_owned()),
+                _ => continue,
+            };
+
+            tokens.push(Token::from_range_inclusive(
+                source,
+                span.byte_offset,
+                cursor - 1,
+                span.start_line,
+                span.start_column,
+                kind,
+            ));
+        }
+
+        let mut depth: usize = 0;
+        for token in &tokens {
+            if token.opens_bracket() {
+                depth += 1;
+            } else if token.closes_bracket() {
+                depth = depth.saturating_sub(1);
+            }
+        }
+
+        let (token_at_cursor, token_before_cursor) = match tokens.split_last() {
+            Some((last, rest)) if last.span.byte_offset + last.lexeme.len() == cursor => {
+                (Some(last), rest.last())
+            }
+            _ => (None, tokens.last()),
+        };
+
+        CompletionContext {
+            prefix: token_at_cursor
+                .map(|token| token.lexeme.to_owned())
+                .unwrap_or_default(),
+            token_before_cursor: token_before_cursor.map(OwnedToken::from),
+            token_at_cursor: token_at_cursor.map(OwnedToken::from),
+            bracket_depth: depth,
+        }
+    }
+
+    pub fn max_line_length(&self) -> usize {
+        self.source.lines().map(str::len).max().unwrap_or(0)
+    }
+
+    pub fn longest_token_lexeme(&self) -> &'a str {
+        self.longest_lexeme
+    }
+
+    pub fn set_source(&mut self, new_source: &'a str) {
+        self.source = new_source;
+        self.tokens.clear();
+        self.tokens_produced = 0;
+        self.last_token_kind = None;
+        self.longest_lexeme = "";
+        self.previous_lexeme = "";
+        self.follows_value = false;
+        self.start = 0;
+        self.current = 0;
+        self.line = 1;
+        self.column = 1;
+    }
+
+    pub fn reset(&mut self, source: &'a str) {
+        self.set_source(source);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn scan_tokens_from_stdin(filename: &str) -> Result<Vec<OwnedToken>, OwnedError> {
+        let source = io::read_to_string(io::stdin()).map_err(|_| OwnedError {
+            inner: Box::new(OwnedErrorInner {
+                lexeme: String::new(),
+                span: Span::new("", 0, 1, 1),
+                context: String::new(),
+                filename: filename.to_owned(),
+                kind: ErrorKind::UnreadableSource,
+                suggestion: None,
+                secondary_spans: Vec::new(),
+            }),
+        })?;
+
+        scan_tokens_reentrant(&source, filename)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn scan_tokens_streaming_to_file<P>(&mut self, output: P) -> Result<u64, ScanIoError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut writer = BufWriter::new(File::create(output)?);
+        let mut written = 0;
+
+        loop {
+            let token = self
+                .next_token()
+                .map_err(|err| ScanIoError::Scan(OwnedError::from(&err)))?;
+            let bytes = token.to_compact_bytes().map_err(ScanIoError::Compact)?;
+
+            writer.write_all(&bytes)?;
+            written += bytes.len() as u64;
+
+            if let TokenKind::Eof = token.kind {
+                break;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(written)
+    }
+
+    pub fn scan_tokens(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        while !self.has_reached_eof() {
+            self.start = self.current;
+
+            self.scan_token()?
+        }
+
+        self.add_eof();
+
+        Ok(&self.tokens)
+    }
+
+    // Scans with a tweaked config for this call only, so the tweak does not
+    // leak into later scans after `reset`.
+    fn scan_tokens_configured<F>(&mut self, configure: F) -> Result<&[Token<'a>], Error<'a, 'b>>
+    where
+        F: FnOnce(&mut TokenizerConfig),
+    {
+        let saved = self.config.clone();
+        configure(&mut self.config);
+
+        let scanned = self.scan_tokens().map(|_| ());
+        self.config = saved;
+        scanned?;
+
+        Ok(&self.tokens)
+    }
+
+    pub fn scan_tokens_without_eof(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        while !self.has_reached_eof() {
+            self.start = self.current;
+
+            self.scan_token()?
+        }
+
+        Ok(&self.tokens)
+    }
+
+    pub fn scan_tokens_all(&mut self) -> (&[Token<'a>], Vec<Error<'a, 'b>>) {
+        let (errors, _) = self.scan_recovering(usize::MAX);
+
+        (&self.tokens, errors)
+    }
+
+    pub fn scan_tokens_line_by_line_interactive(&mut self) -> ScanLineResult<'_, 'a, 'b> {
+        let tokens = match self.scan_tokens() {
+            Ok(tokens) => tokens,
+            Err(error) => {
+                let reason = match error.inner.kind {
+                    ErrorKind::UnterminatedString => IncompleteReason::UnterminatedString,
+                    ErrorKind::UnterminatedHeredoc => IncompleteReason::UnterminatedHeredoc,
+                    ErrorKind::UnterminatedBlockComment => {
+                        IncompleteReason::UnterminatedBlockComment
+                    }
+                    _ => return ScanLineResult::Error(error),
+                };
+
+                return ScanLineResult::Incomplete { reason };
+            }
+        };
+
+        let mut depth: usize = 0;
+        for token in tokens {
+            if token.opens_bracket() {
+                depth += 1;
+            } else if token.closes_bracket() {
+                depth = depth.saturating_sub(1);
+            }
+        }
+
+        if depth > 0 {
+            return ScanLineResult::Incomplete {
+                reason: IncompleteReason::UnclosedBracket { depth },
+            };
+        }
+
+        let last = tokens
+            .iter()
+            .rev()
+            .find(|token| !matches!(token.kind, TokenKind::Eof));
+        if let Some(Token {
+            kind: TokenKind::Operator(_) | TokenKind::CustomOp(_),
+            ..
+        }) = last
+        {
+            return ScanLineResult::Incomplete {
+                reason: IncompleteReason::TrailingOperator,
+            };
+        }
+
+        ScanLineResult::Complete(tokens)
+    }
+
+    pub fn scan_tokens_annotated_with_semantic_groups(
+        &mut self,
+    ) -> Result<Vec<SemanticGroup<'_, 'a>>, Error<'a, 'b>> {
+        let tokens = self.scan_tokens()?;
+        let tokens = match tokens.last() {
+            Some(Token {
+                kind: TokenKind::Eof,
+                ..
+            }) => &tokens[..tokens.len() - 1],
+            _ => tokens,
+        };
+
+        let mut groups = Vec::new();
+        let (mut start, mut depth) = (0, 0usize);
+
+        for (index, token) in tokens.iter().enumerate() {
+            if index > start && depth == 0 {
+                let previous = &tokens[index - 1];
+                let blank_line = token.span.start_line > previous.span.end_line + 1;
+
+                if blank_line || matches!(token.kind, TokenKind::Keyword(_)) {
+                    groups.push(SemanticGroup::new(&tokens[start..index]));
+                    start = index;
+                }
+            }
+
+            if token.opens_bracket() {
+                depth += 1;
+            } else if token.closes_bracket() {
+                depth = depth.saturating_sub(1);
+            }
+        }
+
+        if start < tokens.len() {
+            groups.push(SemanticGroup::new(&tokens[start..]));
+        }
+
+        Ok(groups)
+    }
+
+    pub fn scan_tokens_checking_identifier_length(
+        &mut self,
+        max: usize,
+    ) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        self.scan_tokens_configured(|config| config.max_identifier_length = Some(max))
+    }
+
+    pub fn scan_tokens_checking_max_nesting(
+        &mut self,
+        max_depth: usize,
+    ) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        let mut depth: usize = 0;
+
+        while !self.has_reached_eof() {
+            self.start = self.current;
+            let scanned = self.tokens.len();
+
+            self.scan_token()?;
+
+            for token in &self.tokens[scanned..] {
+                if token.opens_bracket() {
+                    depth += 1;
+                } else if token.closes_bracket() {
+                    depth = depth.saturating_sub(1);
+                }
+
+                if depth > max_depth {
+                    let context = self
+                        .source
+                        .lines()
+                        .nth(token.span.start_line - 1)
+                        .unwrap_or("");
+
+                    return Err(Error::new(
+                        token.lexeme,
+                        token.span,
+                        context,
+                        self.filename,
+                        ErrorKind::NestingDepthExceeded {
+                            depth,
+                            max: max_depth,
+                        },
+                    ));
+                }
+            }
+        }
+
+        self.add_eof();
+
+        Ok(&self.tokens)
+    }
+
+    pub fn scan_tokens_with_token_type_validation(
+        &mut self,
+        schema: &TokenSchema,
+    ) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        let (source, filename) = (self.source, self.filename);
+
+        let tokens = self.scan_tokens()?;
+
+        for pair in tokens.windows(2) {
+            let (after, found) = (pair[0].kind.name(), pair[1].kind.name());
+
+            if !schema.allows(after, found) {
+                let token = &pair[1];
+                let context = source.lines().nth(token.span.start_line - 1).unwrap_or("");
+
+                return Err(Error::new(
+                    token.lexeme,
+                    token.span,
+                    context,
+                    filename,
+                    ErrorKind::UnexpectedTokenSequence { after, found },
+                ));
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    pub fn scan_tokens_with_alias_table(
+        &mut self,
+        aliases: &'a AliasTable,
+    ) -> Result<(&[Token<'a>], Vec<Error<'a, 'b>>), Error<'a, 'b>> {
+        let mut warnings = Vec::new();
+
+        while !self.has_reached_eof() {
+            self.start = self.current;
+
+            let (alias, canonical) = match aliases.longest_match(&self.source[self.current..]) {
+                Some(found) => found,
+                None => {
+                    self.scan_token()?;
+                    continue;
+                }
+            };
+
+            let original = &self.source[self.start..self.start + alias.len()];
+            warnings.push(self.boo(
+                original,
+                ErrorKind::AliasSubstituted {
+                    canonical: canonical.to_owned(),
+                },
+            ));
+
+            let mut rescanned =
+                Tokenizer::with_config(canonical, self.filename, self.config.clone());
+            let kind = match (rescanned.next_token(), rescanned.next_token()) {
+                (
+                    Ok(token),
+                    Ok(Token {
+                        kind: TokenKind::Eof,
+                        ..
+                    }),
+                ) => token.kind,
+                _ => TokenKind::CustomOp(canonical.to_owned()),
+            };
+
+            let right_associative = self.right_associative(&kind, canonical);
+            let precedence = self.operator_precedence(&kind, canonical);
+
+            self.current += alias.len();
+            self.add_token(kind);
+
+            if let Some(token) = self.tokens.last_mut() {
+                token.canonical_lexeme = Some(canonical);
+                token.right_associative = right_associative;
+                token.precedence = precedence;
+            }
+        }
+
+        self.add_eof();
+
+        Ok((&self.tokens, warnings))
+    }
+
+    pub fn scan_tokens_with_shebang_skip(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        if self.source[self.current..].starts_with("#!") {
+            self.start = self.current;
+            self.read_while(|c| c.ne(&'\n'));
+
+            if self.config.include_comments {
+                let shebang = self.source[self.start..self.current].to_owned();
+
+                self.add_token(TokenKind::Shebang(shebang));
+            }
+        }
+
+        self.scan_tokens()
+    }
+
+    pub fn scan_tokens_with_position_assertions(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        let source = self.source;
+        let filename = self.filename;
+        let tokens = self.scan_tokens()?;
+
+        for (index, line) in source.lines().enumerate() {
+            let annotation = match line.find("-- @token") {
+                Some(position) => &line[position + "-- @token".len()..],
+                None => continue,
+            };
+
+            let (kind, lexeme, expected_line, expected_column) =
+                match parse_position_assertion(annotation) {
+                    Some(assertion) => assertion,
+                    None => panic!(
+                        "{}:{}: malformed position assertion `{}`",
+                        filename,
+                        index + 1,
+                        annotation.trim()
+                    ),
+                };
+
+            let found = tokens.iter().any(|token| {
+                token.kind.name().to_ascii_uppercase() == kind
+                    && token.lexeme == lexeme
+                    && token.span.start_line == expected_line
+                    && token.span.start_column == expected_column
+            });
+
+            if !found {
+                let actual = tokens
+                    .iter()
+                    .find(|token| {
+                        token.span.start_line == expected_line
+                            && token.span.start_column == expected_column
+                    })
+                    .or_else(|| tokens.iter().find(|token| token.lexeme == lexeme))
+                    .map_or_else(|| String::from("no such token"), Token::to_ir_string);
+
+                panic!(
+                    "{}:{}: expected {}({})@{}:{}, found {}",
+                    filename,
+                    index + 1,
+                    kind,
+                    lexeme,
+                    expected_line,
+                    expected_column,
+                    actual
+                );
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    pub fn scan_tokens_with_fuzzy_recovery(
+        &mut self,
+    ) -> Result<(&[Token<'a>], Vec<Error<'a, 'b>>), Error<'a, 'b>> {
+        while !self.has_reached_eof() {
+            self.start = self.current;
+
+            self.scan_token()?
+        }
+
+        let mut open = Vec::new();
+        for token in &self.tokens {
+            if token.opens_bracket() {
+                open.push(token.lexeme);
+            } else if token.closes_bracket() {
+                open.pop();
+            }
+        }
+
+        let mut warnings = Vec::new();
+        while let Some(opener) = open.pop() {
+            let closer = if opener == "(" { ")" } else { "}" };
+
+            warnings.push(self.boo(closer, ErrorKind::InsertedToken { lexeme: closer }));
+
+            self.push_token(Token {
+                lexeme: closer,
+                span: Span::new(closer, self.source.len(), self.line, self.column),
+                kind: TokenKind::Bracket(closer),
+                synthetic: true,
+                type_hint: None,
+                has_leading_space: false,
+                has_trailing_space: false,
+                taint: self.taint,
+                canonical_lexeme: None,
+                right_associative: false,
+                precedence: None,
+            });
+        }
+
+        self.add_eof();
+
+        Ok((&self.tokens, warnings))
+    }
+
+    pub fn scan_tokens_type_annotated(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        self.scan_tokens_configured(|config| config.type_hints = true)
+    }
+
+    pub fn scan_tokens_with_taint_tracking(
+        &mut self,
+        taint: TaintLevel,
+    ) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        let saved = std::mem::replace(&mut self.taint, taint);
+
+        let scanned = self.scan_tokens().map(|_| ());
+        self.taint = saved;
+        scanned?;
+
+        Ok(&self.tokens)
+    }
+
+    pub fn scan_tokens_with_heredoc_support(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        self.scan_tokens_configured(|config| config.heredocs = true)
+    }
+
+    pub fn scan_tokens_to_graphviz(&mut self) -> Result<String, Error<'a, 'b>> {
+        let tokens = self.scan_tokens()?;
+
+        let mut dot = String::from("digraph tokens {\n");
+        for (id, token) in tokens.iter().enumerate() {
+            dot.push_str(&format!(
+                "    t{} [label=\"{}@{}:{}\"];\n",
+                id,
+                token.kind.name(),
+                token.span.start_line,
+                token.span.start_column
+            ));
+
+            if id > 0 {
+                dot.push_str(&format!("    t{} -> t{};\n", id - 1, id));
+            }
+        }
+        dot.push_str("}\n");
+
+        Ok(dot)
+    }
+
+    pub fn scan_tokens_preserving_formatting(&mut self) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        self.scan_tokens_configured(|config| config.preserve_whitespace = true)
+    }
+
+    pub fn scan_tokens_with_comment_map(
+        &mut self,
+    ) -> Result<(&[Token<'a>], CommentMap<'_, 'a>), Error<'a, 'b>> {
+        let tokens = self.scan_tokens_configured(|config| config.doc_comments = true)?;
+
+        let mut map = HashMap::new();
+        for (id, token) in tokens.iter().enumerate() {
+            if let TokenKind::DocComment = token.kind {
+                let documented = tokens[id..]
+                    .iter()
+                    .find(|token| !matches!(token.kind, TokenKind::DocComment));
+
+                if let Some(documented) = documented {
+                    if !matches!(documented.kind, TokenKind::Eof) {
+                        map.insert(id as u64, documented);
+                    }
+                }
+            }
+        }
+
+        Ok((tokens, CommentMap(map)))
+    }
+
+    pub fn scan_tokens_with_recovery_log(
+        &mut self,
+    ) -> (&[Token<'a>], Vec<Error<'a, 'b>>, Vec<RecoveryEntry>) {
+        let (errors, log) = self.scan_recovering(usize::MAX);
+
+        (&self.tokens, errors, log)
+    }
+
+    pub fn scan_tokens_with_error_budget(
+        &mut self,
+        max_errors: Option<usize>,
+    ) -> Result<(&[Token<'a>], Vec<Error<'a, 'b>>), Vec<Error<'a, 'b>>> {
+        let (errors, _) = self.scan_recovering(max_errors.unwrap_or(usize::MAX));
+
+        // `Err` means the budget ran out before the end of the source.
+        if !self.has_reached_eof() {
+            return Err(errors);
+        }
+
+        Ok((&self.tokens, errors))
+    }
+
+    pub fn scan_tokens_and_count_by_kind(
+        &mut self,
+    ) -> Result<(HashMap<String, usize>, TokenCounts), Error<'a, 'b>> {
+        let tokens = self.scan_tokens()?;
+
+        let mut by_kind: HashMap<String, usize> = HashMap::new();
+        for token in tokens {
+            if !matches!(token.kind, TokenKind::Eof) {
+                *by_kind.entry(token.kind.name().to_owned()).or_insert(0) += 1;
+            }
+        }
+
+        // Ties go to the alphabetically first kind so the result is stable.
+        let most_common_kind = by_kind
+            .iter()
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+            .map(|(kind, _)| kind.clone());
+
+        let counts = TokenCounts {
+            total_tokens: by_kind.values().sum(),
+            unique_kinds: by_kind.len(),
+            most_common_kind,
+        };
+
+        Ok((by_kind, counts))
+    }
+
+    pub fn scan_tokens_and_intern(
+        &mut self,
+        interner: &mut StringInterner,
+    ) -> Result<Vec<InternedToken>, Error<'a, 'b>> {
+        self.scan_tokens_filter_map(|token| {
+            Some(InternedToken {
+                lexeme_id: interner.get_or_intern(token.lexeme),
+                line: token.span.start_line,
+                column: token.span.start_column,
+                kind: OwnedTokenKind::from(&token.kind),
+            })
+        })
+    }
+
+    pub fn scan_tokens_to_ast_tokens(&mut self) -> Result<Vec<AstToken>, Error<'a, 'b>> {
+        let tokens = self.scan_tokens()?;
+
+        let mut ast_tokens: Vec<AstToken> = Vec::with_capacity(tokens.len());
+        let mut open = Vec::new();
+
+        for (id, token) in tokens.iter().enumerate() {
+            if token.closes_bracket() {
+                open.pop();
+            }
+
+            let parent_id = open.last().copied();
+            if let Some(parent_id) = parent_id {
+                ast_tokens[parent_id as usize].child_ids.push(id as u64);
+            }
+
+            ast_tokens.push(AstToken {
+                token: OwnedToken::from(token),
+                parent_id,
+                child_ids: Vec::new(),
+                depth: open.len() as u32,
+            });
+
+            if token.opens_bracket() {
+                open.push(id as u64);
+            }
+        }
+
+        Ok(ast_tokens)
+    }
+
+    pub fn scan_tokens_with_metrics(
+        &mut self,
+        metrics: &mut TokenizerMetrics,
+    ) -> Result<&[Token<'a>], Error<'a, 'b>> {
+        let bytes = self.source.len() as u64;
+        let started = Instant::now();
+
+        let result = self.scan_tokens();
+
+        metrics.total_bytes += bytes;
+        metrics.total_time_ns += started.elapsed().as_nanos() as u64;
+        match &result {
+            Ok(tokens) => metrics.total_tokens += tokens.len() as u64,
+            Err(_) => metrics.total_errors += 1,
+        }
+
+        result
+    }
+
+    pub fn scan_tokens_cached<'c>(
+        &mut self,
+        cache: &'c mut TokenCache,
+    ) -> Result<&'c [OwnedToken], Error<'a, 'b>> {
+        let source_hash = source_hash(self.source);
+        let config = (self.config.clone(), self.taint);
+
+        if cache.source_hash != source_hash || cache.config.as_ref() != Some(&config) {
+            let tokens = self.scan_tokens()?;
+
+            cache.tokens = tokens.iter().map(OwnedToken::from).collect();
+            cache.source_hash = source_hash;
+            cache.config = Some(config);
+        }
+
+        Ok(&cache.tokens)
+    }
+
+    pub fn scan_tokens_filter_map<F, T>(&mut self, mut f: F) -> Result<Vec<T>, Error<'a, 'b>>
+    where
+        F: FnMut(Token<'a>) -> Option<T>,
+    {
+        let mut mapped = Vec::new();
+
+        loop {
+            let token = self.next_token()?;
+            let eof = matches!(token.kind, TokenKind::Eof);
+
+            mapped.extend(f(token));
+
+            if eof {
+                return Ok(mapped);
+            }
+        }
+    }
+
+    pub fn scan_tokens_owned(&mut self) -> Result<Vec<OwnedToken>, Error<'a, 'b>> {
+        self.scan_tokens_filter_map(|token| Some(OwnedToken::from(token)))
+    }
+
+    pub fn scan_tokens_reusing_allocations(
+        &mut self,
+        mut previous: Vec<OwnedToken>,
+    ) -> Result<Vec<OwnedToken>, OwnedError> {
+        previous.clear();
+
+        loop {
+            let token = self.next_token().map_err(|err| OwnedError::from(&err))?;
+            let done = matches!(token.kind, TokenKind::Eof);
+
+            previous.push(OwnedToken::from(&token));
+
+            if done {
+                return Ok(previous);
+            }
+        }
+    }
+
+    pub fn scan_into_stream(&mut self) -> Result<TokenStream<'a>, Error<'a, 'b>>
+    where
+        'b: 'a,
+    {
+        let tokens = self.collect_tokens()?;
+
+        Ok(TokenStream::new(tokens, self.source, self.filename))
+    }
+
+    pub fn scan_tokens_with_span_table(
+        &mut self,
+    ) -> Result<(TokenStream<'a>, SpanTable), Error<'a, 'b>>
+    where
+        'b: 'a,
+    {
+        let tokens = self.collect_tokens()?;
+        let spans = SpanTable::from(tokens.as_slice());
+
+        Ok((TokenStream::new(tokens, self.source, self.filename), spans))
+    }
+
+    pub fn scan_tokens_via_channel(mut self, tx: Sender<Result<OwnedToken, OwnedError>>) {
+        loop {
+            let (message, done) = match self.next_token() {
+                Ok(token) => (
+                    Ok(OwnedToken::from(&token)),
+                    matches!(token.kind, TokenKind::Eof),
+                ),
+                Err(error) => (Err(OwnedError::from(&error)), true),
+            };
+
+            if tx.send(message).is_err() || done {
+                return;
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token<'a>, Error<'a, 'b>> {
+        if self.tokens.is_empty() {
+            self.scan_next()?;
+        }
+
+        Ok(self.tokens.remove(0))
+    }
+
+    pub fn try_scan_token(&mut self) -> Option<Result<OwnedToken, OwnedError>> {
+        match self.next_token() {
+            Ok(Token {
+                kind: TokenKind::Eof,
+                ..
+            }) => None,
+            Ok(token) => Some(Ok(OwnedToken::from(&token))),
+            Err(error) => {
+                let error = OwnedError::from(&error);
+                self.recover();
+
+                Some(Err(error))
+            }
+        }
+    }
+
+    pub fn lookahead(&mut self, n: usize) -> Option<&Token<'a>> {
+        while self.tokens.len() <= n {
+            if let Some(Token {
+                kind: TokenKind::Eof,
+                ..
+            }) = self.tokens.last()
+            {
+                return None;
+            }
+
+            if self.scan_next().is_err() {
+                self.current = self.start;
+
+                return None;
+            }
+        }
+
+        self.tokens.get(n)
+    }
+
+    fn scan_recovering(&mut self, max_errors: usize) -> (Vec<Error<'a, 'b>>, Vec<RecoveryEntry>) {
+        let mut errors = Vec::new();
+        let mut log = Vec::new();
+
+        while !self.has_reached_eof() {
+            self.start = self.current;
+
+            if let Err(error) = self.scan_token() {
+                errors.push(error);
+                log.push(self.recover());
+
+                if errors.len() >= max_errors && !self.has_reached_eof() {
+                    return (errors, log);
+                }
+            }
+        }
+
+        self.add_eof();
+
+        (errors, log)
+    }
+
+    fn recover(&mut self) -> RecoveryEntry {
+        let skipped = &self.source[self.start..self.current];
+
+        let mut characters = skipped.chars();
+        let action = match (characters.next(), characters.next()) {
+            (Some(character), None) => RecoveryAction::SkipCharacter(character),
+            _ => RecoveryAction::DiscardLexeme(skipped.len()),
+        };
+
+        let entry = RecoveryEntry {
+            position: self.start,
+            line: self.line,
+            column: self.column,
+            action,
+        };
+
+        self.column += skipped.chars().count();
+
+        entry
+    }
+
+    fn collect_tokens(&mut self) -> Result<Vec<Token<'a>>, Error<'a, 'b>> {
+        self.scan_tokens_filter_map(Some)
+    }
+
+    fn scan_next(&mut self) -> Result<(), Error<'a, 'b>> {
+        let buffered = self.tokens.len();
+
+        while self.tokens.len() == buffered {
+            if self.has_reached_eof() {
+                self.add_eof();
+
+                break;
+            }
+
+            self.start = self.current;
+
+            self.scan_token()?
+        }
+
+        Ok(())
+    }
+
+    fn has_reached_eof(&self) -> bool {
+        self.current >= self.source.len()
+    }
+
+    fn scan_token(&mut self) -> Result<(), Error<'a, 'b>> {
+        let character = self.advance(1);
+
+        if self.config.allow_custom_ops && self.custom_operator() {
+            return Ok(());
+        }
+
+        match character.chars().next().unwrap() {
+            '(' | ')' => self.add_token(TokenKind::Bracket(character)),
+            '{' => {
+                if self.match_next('-', false) {
+                    let mut depth: usize = 1;
+
+                    while depth > 0 {
+                        if self.match_next_multiple("{-", false) {
+                            depth += 1;
+                        } else if self.match_next_multiple("-}", false) {
+                            depth -= 1;
+                        } else if self.has_reached_eof() {
+                            let lexeme = &self.source[self.start..self.current];
+
+                            return Err(self.boo(lexeme, ErrorKind::UnterminatedBlockComment));
+                        } else {
+                            self.advance(1);
+                        }
+                    }
+
+                    let comment = &self.source[self.start..self.current];
+                    if comment.contains('\n') {
+                        self.track_newlines();
+                    } else {
+                        self.column += comment.chars().count();
+                    }
+                } else {
+                    self.add_token(TokenKind::Bracket(character))
+                }
+            }
+            '}' => self.add_token(TokenKind::Bracket(character)),
+            '<' if self.starts_heredoc() => self.heredoc()?,
+            '<' | '>' => {
+                if self.match_next('=', false) {
+                    self.operator()
+                } else {
+                    self.add_token(TokenKind::Bracket(character))
+                }
+            }
+            '.' if self.match_next_multiple("..", false) => {
+                let kind = if self.has_reached_eof() || self.peek().is_whitespace() {
+                    TokenKind::Ellipsis
+                } else {
+                    TokenKind::Spread
+                };
+                self.add_token(kind)
+            }
+            punctuation @ (',' | '.' | ';') => self.add_token(TokenKind::Punctuation(punctuation)),
+            '"' => self.string()?,
+            'a'..='z' | 'A'..='Z' | '_' => self.identifier()?,
+            '-' => {
+                if self.match_next('-', false) {
+                    let doc_comment = self.config.doc_comments && self.peek() == '-';
+
+                    self.read_while(|c| c.ne(&'\n'));
+
+                    if doc_comment {
+                        self.add_token(TokenKind::DocComment)
+                    }
+                } else {
+                    self.match_next('>', false);
+                    self.operator()
+                }
+            }
+
+            '/' if self.starts_regex() => self.regex()?,
+            '+' | '/' => self.operator(),
+            '*' => {
+                self.match_next('*', false);
+                self.operator()
+            }
+            '!' => {
+                self.match_next('=', false);
+                self.operator()
+            }
+            '&' if self.match_next('&', false) => self.operator(),
+            '|' if self.match_next('|', false) => self.operator(),
+            ':' if self.match_next(':', false) => self.operator(),
+            '=' => {
+                if self.match_next('=', false) || self.match_next('>', false) {
+                    self.operator()
+                } else {
+                    self.add_token(TokenKind::Bracket(character))
+                }
+            }
+            '0' => self.leading_zero_number()?,
+            '1'..='9' => self.number()?,
+            ' ' | '\t' if self.config.preserve_whitespace => {
+                self.read_while(|c| c == ' ' || c == '\t');
+                self.add_token(TokenKind::Whitespace)
+            }
+            '\r' | '\n' if self.config.preserve_whitespace => self.newlines(),
+            ' ' | '\t' => self.column += 1,
+
+            '\r' => {}
+            '\n' => {
+                self.column = 1;
+                self.line += 1;
+            }
+            _ => Err(self.boo(character, ErrorKind::UnknownCharacter))?,
+        };
+
+        Ok(())
+    }
+
+    fn advance(&mut self, advance_by: usize) -> &'a str {
+        let start = self.current;
+        self.current = self.char_boundary_after(advance_by);
+
+        &self.source[start..self.current]
+    }
+
+    // Byte offset reached by moving `chars` characters forward, clamped to
+    // the end of the source.
+    fn char_boundary_after(&self, chars: usize) -> usize {
+        let rest = &self.source[self.current..];
+
+        self.current
+            + rest
+                .char_indices()
+                .nth(chars)
+                .map_or(rest.len(), |(index, _)| index)
+    }
+
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
+    }
+
+    pub fn peek_char_at_offset(&self, offset: usize) -> char {
+        if self.has_reached_eof() {
+            return '\0';
+        }
+
+        self.source[self.current..]
+            .chars()
+            .nth(offset)
+            .unwrap_or('\0')
+    }
+
+    fn peek_str(&self, length: usize) -> &'a str {
+        if self.has_reached_eof() {
+            return "";
+        }
+
+        &self.source[self.current..self.char_boundary_after(length)]
+    }
+
+    fn match_next(&mut self, expected: char, lowercase: bool) -> bool {
+        if self.has_reached_eof() {
+            return false;
+        }
+
+        let character = self.peek();
+        if !lowercase && character != expected {
+            return false;
+        }
+        if lowercase
+            && character
+                .to_ascii_lowercase()
+                .ne(&expected.to_ascii_lowercase())
+        {
+            return false;
+        }
+
+        self.current += character.len_utf8();
+        true
+    }
+
+    fn match_next_predicate<P>(&mut self, predicate: P) -> bool
+    where
+        P: Fn(char) -> bool,
+    {
+        if self.has_reached_eof() {
+            return false;
+        }
+
+        let character = self.peek();
+        if !predicate(character) {
+            return false;
+        }
+
+        self.current += character.len_utf8();
+        true
+    }
+
+    fn match_next_multiple(&mut self, expected: &str, lowercase: bool) -> bool {
+        let current = self.current;
+
+        if expected.chars().all(|c| self.match_next(c, lowercase)) {
+            return true;
+        }
+
+        self.current = current;
+        false
+    }
+
+    fn read_while<P>(&mut self, predicate: P)
+    where
+        P: Fn(char) -> bool,
+    {
+        while predicate(self.peek()) && !self.has_reached_eof() {
+            self.advance(1);
+        }
+    }
+
+    fn add_token(&mut self, kind: TokenKind<'a>) {
+        let lexeme = &self.source[self.start..self.current];
+        let (has_leading_space, has_trailing_space) =
+            surrounding_space(self.source, self.start, self.current);
+
+        let mut token = Token {
+            lexeme,
+            span: Span::new(lexeme, self.start, self.line, self.column),
+            kind,
+            synthetic: false,
+            type_hint: None,
+            has_leading_space,
+            has_trailing_space,
+            taint: self.taint,
+            canonical_lexeme: None,
+            right_associative: false,
+            precedence: None,
+        };
+
+        token.right_associative = self.right_associative(&token.kind, lexeme);
+        token.precedence = self.operator_precedence(&token.kind, lexeme);
+
+        if self.config.type_hints {
+            token.type_hint = match token.kind {
+                TokenKind::String(_) | TokenKind::HeredocLiteral { .. } => Some(TypeHint::String),
+                TokenKind::Boolean(_) => Some(TypeHint::Bool),
+                _ => token.classify_number().map(|class| match class {
+                    NumberClass::Float => TypeHint::Float,
+                    NumberClass::BigInt => TypeHint::BigInt,
+                    _ => TypeHint::Integer,
+                }),
+            };
+        }
+
+        // Kept outside `tokens` so that draining it one token at a time
+        // (`next_token`, the iterator) doesn't change how `/` is read.
+        if !matches!(token.kind, TokenKind::Whitespace | TokenKind::Newline(_)) {
+            self.previous_lexeme = lexeme;
+            self.follows_value = match token.kind {
+                TokenKind::Number(_)
+                | TokenKind::BigInt(_)
+                | TokenKind::RegexLiteral(_)
+                | TokenKind::String(_)
+                | TokenKind::HeredocLiteral { .. }
+                | TokenKind::Boolean(_)
+                | TokenKind::Null
+                | TokenKind::Identifier(_) => true,
+                TokenKind::Bracket(bracket) => matches!(bracket, ")" | "}" | ">"),
+                _ => false,
+            };
+        }
+
+        if lexeme.len() > self.longest_lexeme.len() {
+            self.longest_lexeme = lexeme;
+        }
+
+        self.column += lexeme.chars().count();
+        self.push_token(token);
+    }
+
+    // `=`, `<` and `>` come out as brackets, so only real operators are
+    // looked up, plus the assignment `=` for associativity.
+    fn right_associative(&self, kind: &TokenKind, lexeme: &str) -> bool {
+        matches!(
+            kind,
+            TokenKind::Operator(_) | TokenKind::CustomOp(_) | TokenKind::Bracket("=")
+        ) && self.config.right_associative_ops.contains(lexeme)
+    }
+
+    fn operator_precedence(&self, kind: &TokenKind, lexeme: &str) -> Option<u8> {
+        match kind {
+            TokenKind::Operator(_) | TokenKind::CustomOp(_) => {
+                self.config.operator_precedences.get(lexeme).copied()
+            }
+            _ => None,
+        }
+    }
+
+    fn push_token(&mut self, token: Token<'a>) {
+        self.tokens_produced += 1;
+        self.last_token_kind = Some(token.kind.name());
+        self.tokens.push(token);
+    }
+
+    fn add_eof(&mut self) {
+        self.push_token(Token {
+            lexeme: "",
+            span: Span::new("", self.source.len(), self.line, 0),
+            kind: TokenKind::Eof,
+            synthetic: false,
+            type_hint: None,
+            has_leading_space: false,
+            has_trailing_space: false,
+            taint: self.taint,
+            canonical_lexeme: None,
+            right_associative: false,
+            precedence: None,
+        });
+    }
+
+    // Digits in `radix`, with `_` allowed only between two of them.
+    fn digits(&mut self, radix: u32) -> Result<(), Error<'a, 'b>> {
+        loop {
+            self.read_while(|c| c.is_digit(radix));
+
+            if !self.match_next('_', false) {
+                return Ok(());
+            }
+            if self.has_reached_eof() || !self.peek().is_digit(radix) {
+                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<(), Error<'a, 'b>> {
+        self.digits(10)?;
+
+        let mut is_float = false;
+
+        if self.match_next('.', false) {
+            if self.match_next_predicate(|c| c.is_ascii_digit()) {
+                is_float = true;
+                self.digits(10)?
+            } else {
+                self.current -= 1;
+            }
+        }
+        if self.match_next('e', true) {
+            is_float = true;
+
+            if !self.match_next('-', false) {
+                self.match_next('+', false);
+            }
+
+            if self.match_next_predicate(|c| c.is_ascii_digit()) {
+                self.digits(10)?
+            } else {
+                let lexeme = &self.source[self.start..self.current];
+
+                return Err(self.boo(lexeme, ErrorKind::IncompleteExponent));
+            }
+        }
+
+        let digits = self.source[self.start..self.current].replace('_', "");
+
+        let bigint = if is_float {
+            false
+        } else {
+            self.match_next('n', false)
+        };
+        if bigint {
+            let literal = digits.parse::<BigInt>().unwrap();
+
+            self.add_token(TokenKind::BigInt(literal));
+
+            return Ok(());
+        }
+
+        let literal = digits.parse::<f64>().unwrap();
+
+        self.add_token(TokenKind::Number(literal));
+
+        Ok(())
+    }
+
+    fn leading_zero_number(&mut self) -> Result<(), Error<'a, 'b>> {
+        if self.match_next('b', true) {
+            if self.match_next_predicate(|c| c.is_digit(2)) {
+                self.digits(2)?
+            } else {
+                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+            }
+        } else if self.match_next('o', true) {
+            if self.match_next_predicate(|c| c.is_digit(8)) {
+                self.digits(8)?
+            } else {
+                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+            }
+        } else if self.match_next('x', false) {
+            if self.match_next_predicate(|c| c.is_ascii_hexdigit()) {
+                self.digits(16)?
+            } else {
+                return Err(self.boo(self.peek_str(1), ErrorKind::ExpectedDigit));
+            }
+        } else {
+            return self.number();
+        }
+
+        let lexeme = &self.source[self.start..self.current];
+
+        let second_char = lexeme.to_ascii_lowercase().chars().nth(1).unwrap();
+        let digits = lexeme[2..].replace('_', "");
+
+        let bigint = self.match_next('n', false);
+        if bigint {
+            let literal = match second_char {
+                'b' => u64::from_str_radix(&digits, 2),
+                'o' => u64::from_str_radix(&digits, 8),
+                _ => u64::from_str_radix(&digits, 16),
+            }
+            .unwrap()
+            .to_bigint()
+            .unwrap();
+
+            self.add_token(TokenKind::BigInt(literal));
+
+            return Ok(());
+        }
+
+        let literal = match second_char {
+            'b' => u64::from_str_radix(&digits, 2),
+            'o' => u64::from_str_radix(&digits, 8),
+            _ => u64::from_str_radix(&digits, 16),
+        }
+        .unwrap() as f64;
+
+        self.add_token(TokenKind::Number(literal));
+
+        Ok(())
+    }
+
+    fn custom_operator(&mut self) -> bool {
+        let rest = &self.source[self.start..];
+
+        let mut length = 0;
+        for (index, character) in rest.char_indices() {
+            if !"!@#$%^&*+-=|<>?/~".contains(character) || rest[index..].starts_with("--") {
+                break;
+            }
+
+            length = index + character.len_utf8();
+        }
+
+        let run = &rest[..length];
+        if length == 0
+            || matches!(
+                run,
+                "!" | "*"
+                    | "+"
+                    | "-"
+                    | "/"
+                    | "="
+                    | "<"
+                    | ">"
+                    | "<="
+                    | ">="
+                    | "=="
+                    | "->"
+                    | "=>"
+                    | "**"
+                    | "&&"
+                    | "||"
+                    | "!="
+            )
+        {
+            return false;
+        }
+
+        self.current = self.start + length;
+        self.add_token(TokenKind::CustomOp(run.to_owned()));
+
+        true
+    }
+
+    fn starts_regex(&self) -> bool {
+        if !self.config.regex_literals || self.has_reached_eof() {
+            return false;
+        }
+
+        let next = self.peek();
+        if next.is_whitespace() || next == '/' {
+            return false;
+        }
+
+        !self.follows_value
+    }
+
+    fn regex(&mut self) -> Result<(), Error<'a, 'b>> {
+        loop {
+            if self.has_reached_eof() || self.peek() == '\n' {
+                let lexeme = &self.source[self.start..self.current];
+
+                return Err(self.boo(lexeme, ErrorKind::UnterminatedRegex));
+            }
+
+            match self.advance(1) {
+                "\\" if !self.has_reached_eof() => {
+                    self.advance(1);
+                }
+                "/" => break,
+                _ => {}
+            }
+        }
+
+        let pattern = self.source[self.start + 1..self.current - 1].to_owned();
+
+        self.read_while(|c| matches!(c, 'g' | 'i' | 'm' | 's' | 'u'));
+
+        self.add_token(TokenKind::RegexLiteral(pattern));
+
+        Ok(())
+    }
+
+    fn operator(&mut self) {
+        let lexeme = &self.source[self.start..self.current];
+
+        self.add_token(TokenKind::Operator(lexeme));
+    }
+
+    fn identifier(&mut self) -> Result<(), Error<'a, 'b>> {
+        // Identifiers swallow their own digits, so a digit right before us
+        // can only be the tail of a number literal, as in `0X1` or `12px`.
+        if self.source[..self.start].ends_with(|c: char| c.is_ascii_digit()) {
+            let lexeme = &self.source[self.start..self.current];
+
+            return Err(self.boo(lexeme, ErrorKind::UnknownCharacter));
+        }
+
+        self.read_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        let name = &self.source[self.start..self.current];
+
+        if let Some(max) = self.config.max_identifier_length {
+            if name.len() > max {
+                return Err(self.boo(
+                    name,
+                    ErrorKind::IdentifierTooLong {
+                        length: name.len(),
+                        max,
+                    },
+                ));
+            }
+        }
+
+        if name == "true" || name == "false" {
+            self.add_token(TokenKind::Boolean(name == "true"));
+        } else if name == "null" {
+            self.add_token(TokenKind::Null);
+        } else if self.config.keywords.iter().any(|keyword| keyword == name) {
+            self.add_token(TokenKind::Keyword(name));
+        } else {
+            self.add_token(TokenKind::Identifier(name));
+        }
+
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<(), Error<'a, 'b>> {
+        loop {
+            if self.has_reached_eof() {
+                let lexeme = &self.source[self.start..self.current];
+
+                return Err(self.boo(lexeme, ErrorKind::UnterminatedString));
+            }
+
+            if self.advance(1) == "\"" {
+                break;
+            }
+        }
+
+        let contents = unescape_string(&self.source[self.start + 1..self.current - 1]);
+
+        self.add_token(TokenKind::String(contents));
+        self.track_newlines();
+
+        Ok(())
+    }
+
+    fn starts_heredoc(&self) -> bool {
+        if !self.config.heredocs {
+            return false;
+        }
+
+        let rest = &self.source[self.current..];
+        let tag = rest.strip_prefix("<-").or_else(|| rest.strip_prefix('<'));
+
+        tag.and_then(|tag| tag.chars().next())
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    }
+
+    // The body starts on the line after the tag; the rest of the opening
+    // line is kept in the lexeme but not in the content.
+    fn heredoc(&mut self) -> Result<(), Error<'a, 'b>> {
+        self.advance(1);
+        let indented = self.match_next('-', false);
+
+        let tag_start = self.current;
+        self.read_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let tag = &self.source[tag_start..self.current];
+
+        let mut lines = Vec::new();
+        loop {
+            self.current = self.source[self.current..]
+                .find('\n')
+                .map_or(self.source.len(), |end| self.current + end);
+
+            if self.has_reached_eof() {
+                let lexeme = &self.source[self.start..self.current];
+
+                return Err(self.boo(lexeme, ErrorKind::UnterminatedHeredoc));
+            }
+
+            self.advance(1);
+
+            let line_start = self.current;
+            let line_end = self.source[line_start..]
+                .find('\n')
+                .map_or(self.source.len(), |end| line_start + end);
+
+            let line = &self.source[line_start..line_end];
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let line = if indented { line.trim_start() } else { line };
+
+            if line == tag || line.strip_suffix(';') == Some(tag) {
+                self.current = line_end;
+                break;
+            }
+
+            lines.push(line);
+        }
+
+        self.add_token(TokenKind::HeredocLiteral {
+            tag: tag.to_owned(),
+            content: lines.join("\n"),
+        });
+        self.track_newlines();
+
+        Ok(())
+    }
+
+    fn newlines(&mut self) {
+        loop {
+            self.read_while(|c| c == '\r' || c == '\n');
+
+            // Indentation on an otherwise blank line is part of the run, so
+            // a paragraph break stays a single `Newline` token.
+            let rest = &self.source[self.current..];
+            let indent = rest.len() - rest.trim_start_matches(&[' ', '\t'][..]).len();
+            if !rest[indent..].starts_with(&['\r', '\n'][..]) {
+                break;
+            }
+
+            self.current += indent;
+        }
+
+        let count = self.source[self.start..self.current].matches('\n').count();
+        if count == 0 {
+            self.add_token(TokenKind::Whitespace);
+        } else {
+            self.add_token(TokenKind::Newline(count));
+            self.line += count;
+            self.column = 1;
+        }
+    }
+
+    fn track_newlines(&mut self) {
+        let lexeme = &self.source[self.start..self.current];
+
+        if let Some(last_newline) = lexeme.rfind('\n') {
+            self.line += lexeme.matches('\n').count();
+            self.column = lexeme[last_newline..].chars().count();
+        }
+    }
+
+    fn boo(&self, lexeme: &'a str, kind: ErrorKind) -> Error<'a, 'b> {
+        let line = self.source.lines().nth(self.line - 1).unwrap_or("");
+        let suggestion = self.suggest(lexeme, &kind);
+
+        Error::new_with_suggestion(
+            lexeme,
+            Span::new(lexeme, self.start, self.line, self.column),
+            line,
+            self.filename,
+            kind,
+            suggestion,
+        )
+    }
+
+    fn suggest(&self, lexeme: &str, kind: &ErrorKind) -> Option<String> {
+        match kind {
+            ErrorKind::UnknownCharacter => {
+                let after_lone_zero =
+                    self.source[..self.start].ends_with('0') && self.previous_lexeme == "0";
+                let radix = lexeme.to_ascii_lowercase();
+
+                if after_lone_zero && matches!(radix.as_str(), "b" | "o" | "x") {
+                    Some(format!("0{}", radix))
+                } else {
+                    None
+                }
+            }
+            ErrorKind::IncompleteExponent => Some(format!("{}0", lexeme)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, 'b> Iterator for Tokenizer<'a, 'b> {
+    type Item = Result<Token<'a>, Error<'a, 'b>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(Token {
+                kind: TokenKind::Eof,
+                ..
+            }) => None,
+            Ok(token) => Some(Ok(token)),
+            Err(error) => {
+                self.recover();
+
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+fn parse_position_assertion(annotation: &str) -> Option<(&str, &str, usize, usize)> {
+    let mut words = annotation.split_whitespace();
+    let kind = words.next()?;
+    let lexeme = words.next()?;
+
+    let (mut line, mut column) = (None, None);
+    while let Some(word) = words.next() {
+        let value = words.next()?.parse::<usize>().ok()?;
+
+        match word {
+            "@line" => line = Some(value),
+            "@col" => column = Some(value),
+            _ => return None,
+        }
+    }
+
+    Some((kind, lexeme, line?, column?))
+}
+
+const COMPACT_FORMAT_VERSION: u8 = 1;
+
+fn write_diagnostic<W>(out: &mut W, [header, snippet, footer]: [String; 3]) -> std::fmt::Result
+where
+    W: std::fmt::Write,
+{
+    write!(out, "{}\n{}", header, snippet)?;
+
+    if !footer.is_empty() {
+        write!(out, "\n\n{}", footer)?;
+    }
+
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            _ => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+// Escape sequences are not processed yet; the contents are taken verbatim.
+fn unescape_string(raw: &str) -> String {
+    raw.to_owned()
+}
+
+fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn surrounding_space(source: &str, start: usize, end: usize) -> (bool, bool) {
+    (
+        source[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace),
+        source[end..]
+            .chars()
+            .next()
+            .is_some_and(char::is_whitespace),
+    )
+}
+
+pub fn run<'a>(tokenizer: &'a mut Tokenizer<'a, 'a>) -> Result<&'a [Token<'a>], Error<'a, 'a>> {
+    tokenizer.scan_tokens()
+}
+
+pub fn scan_tokens_reentrant(source: &str, filename: &str) -> Result<Vec<OwnedToken>, OwnedError> {
+    let mut tokenizer = Tokenizer::new(source, filename);
+    let tokens = tokenizer
+        .scan_tokens()
+        .map_err(|err| OwnedError::from(&err))?;
+
+    Ok(tokens.iter().map(OwnedToken::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds<'a>(source: &'a str, config: TokenizerConfig) -> Vec<TokenKind<'a>> {
+        Tokenizer::with_config(source, "test", config)
+            .map(|token| token.unwrap().kind)
+            .collect()
+    }
+
+    #[test]
+    fn alias_substitution_keeps_source_lexeme() {
+        let source = "a ≠ b";
+        let mut aliases = AliasTable::new();
+        aliases.insert("≠", "!=");
+
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let (tokens, warnings) = tokenizer.scan_tokens_with_alias_table(&aliases).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(tokens[1].lexeme(), "≠");
+        assert_eq!(tokens[1].original_lexeme(), Some("≠"));
+        assert_eq!(tokens[1].canonical_lexeme(), "!=");
+        assert_eq!(tokens[1].kind(), &TokenKind::Operator("!="));
+
+        assert_eq!(
+            TokenStream::new(tokens.to_vec(), source, "test").to_source_text(source),
+            source
+        );
+        assert_eq!(Token::expand_whitespace(tokens, source).len(), 6);
+        assert_eq!(SpanTable::from(tokens).spans[1], (2, 5, 1, 3));
+    }
+
+    #[test]
+    fn division_is_not_a_regex_when_iterating() {
+        let source = "a/b/c";
+        let config = || TokenizerConfig {
+            regex_literals: true,
+            ..TokenizerConfig::default()
+        };
+
+        let mut tokenizer = Tokenizer::with_config(source, "test", config());
+        let scanned: Vec<_> = tokenizer
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|token| token.kind().clone())
+            .collect();
+        let iterated: Vec<_> = Tokenizer::with_config(source, "test", config())
+            .map(|token| token.unwrap().kind)
+            .collect();
+
+        assert_eq!(scanned[1], TokenKind::Operator("/"));
+        assert_eq!(scanned[3], TokenKind::Operator("/"));
+        assert_eq!(&scanned[..scanned.len() - 1], &iterated[..]);
+    }
+
+    #[test]
+    fn identifier_tokens() {
+        let mut tokenizer = Tokenizer::new("foo123(bar)+baz", "test");
+        let kinds: Vec<_> = tokenizer
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|token| token.kind().clone())
+            .collect();
+
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Identifier("foo123"),
+                TokenKind::Bracket("("),
+                TokenKind::Identifier("bar"),
+                TokenKind::Bracket(")"),
+                TokenKind::Operator("+"),
+                TokenKind::Identifier("baz"),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn letter_after_number_is_unknown_character() {
+        let mut tokenizer = Tokenizer::new("0X1", "test");
+        let error = tokenizer.scan_tokens().unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::UnknownCharacter);
+        assert_eq!(error.span().start_column, 2);
+        assert!(error.to_plain_string().contains("did you mean `0x`?"));
+    }
+
+    #[test]
+    fn reset_reuses_the_token_buffer() {
+        let mut tokenizer = Tokenizer::new("a + b * c", "test");
+        assert_eq!(tokenizer.scan_tokens().unwrap().len(), 6);
+        let capacity = tokenizer.tokens.capacity();
+
+        tokenizer.reset("d");
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind(), &TokenKind::Identifier("d"));
+        assert_eq!(tokens[0].span(), Span::new("d", 0, 1, 1));
+        assert_eq!(tokenizer.tokens.capacity(), capacity);
+
+        tokenizer.reset("e -");
+        let (tokens, errors) = tokenizer.scan_tokens_all();
+
+        assert!(errors.is_empty());
+        assert_eq!(tokens[0].kind(), &TokenKind::Identifier("e"));
+        assert_eq!(tokenizer.tokens.capacity(), capacity);
+    }
+
+    #[test]
+    fn post_scan_queries_survive_streaming() {
+        let source = "x = longest + y";
+
+        let mut tokenizer = Tokenizer::new(source, "test");
+        tokenizer.scan_tokens().unwrap();
+        assert_eq!(tokenizer.longest_token_lexeme(), "longest");
+
+        tokenizer.reset(source);
+        let (tokens, _) = tokenizer.scan_tokens_all();
+        assert!(!tokens.is_empty());
+        assert_eq!(tokenizer.longest_token_lexeme(), "longest");
+
+        let mut tokenizer = Tokenizer::new(source, "test");
+        while tokenizer.next().is_some() {}
+        let state = tokenizer.dump_state();
+
+        assert_eq!(tokenizer.longest_token_lexeme(), "longest");
+        assert_eq!(state.tokens_produced, 6);
+        assert_eq!(state.last_token_kind.as_deref(), Some("eof"));
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let mut tokenizer = Tokenizer::new("{- a {- b {- c -} -} still comment -} z", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind(), &TokenKind::Identifier("z"));
+        assert_eq!(tokens[0].column(), 39);
+
+        let mut tokenizer = Tokenizer::new("{- a {- b -} z", "test");
+        let error = tokenizer.scan_tokens().unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::UnterminatedBlockComment);
+        assert_eq!(error.span().start_column, 1);
+    }
+
+    #[test]
+    fn block_comments_advance_position() {
+        let mut tokenizer = Tokenizer::new("{- c -} z", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        assert_eq!((tokens[0].line(), tokens[0].column()), (1, 9));
+
+        let mut tokenizer = Tokenizer::new("{- one\ntwo -} y\nz", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        assert_eq!((tokens[0].line(), tokens[0].column()), (2, 8));
+        assert_eq!((tokens[1].line(), tokens[1].column()), (3, 1));
+    }
+
+    #[test]
+    fn right_associative_operators() {
+        let mut tokenizer = Tokenizer::new("a = b ** c -> d < e", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+        let flags: Vec<_> = tokens.iter().map(Token::is_right_associative).collect();
+
+        assert_eq!(
+            flags,
+            [false, true, false, true, false, true, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn only_operators_have_precedence() {
+        let mut tokenizer = Tokenizer::new("a < b = c + d * e", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+        let precedences: Vec<_> = tokens.iter().map(Token::operator_precedence).collect();
+
+        assert_eq!(
+            precedences,
+            [
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(11),
+                None,
+                Some(12),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn token_schema_rejects_disallowed_successor() {
+        let schema = TokenSchema::new()
+            .rule("number", &["operator", "eof"])
+            .rule("operator", &["number"]);
+
+        let mut tokenizer = Tokenizer::new("1 + 2 * 3", "test");
+        assert!(tokenizer
+            .scan_tokens_with_token_type_validation(&schema)
+            .is_ok());
+
+        let mut tokenizer = Tokenizer::new("1 + + 2", "test");
+        let error = tokenizer
+            .scan_tokens_with_token_type_validation(&schema)
+            .unwrap_err();
+
+        assert_eq!(
+            error.kind(),
+            &ErrorKind::UnexpectedTokenSequence {
+                after: "operator",
+                found: "operator",
+            }
+        );
+        assert_eq!(error.span().start_column, 5);
+    }
+
+    #[test]
+    fn completion_keeps_unterminated_string_at_cursor() {
+        let source = "foo(\"hel";
+        let context = Tokenizer::new(source, "test").scan_tokens_for_completion(source.len());
+
+        assert_eq!(context.prefix(), "\"hel");
+        assert_eq!(context.bracket_depth(), 1);
+
+        let token = context.token_at_cursor().unwrap();
+        assert_eq!(token.kind, OwnedTokenKind::String("hel".to_owned()));
+        assert_eq!(
+            context.token_before_cursor().map(|token| &token.kind),
+            Some(&OwnedTokenKind::Bracket("(".to_owned()))
+        );
+    }
+
+    #[test]
+    fn fuzzy_recovery_closes_open_brackets() {
+        let mut tokenizer = Tokenizer::new("f(a, {b", "test");
+        let (tokens, warnings) = tokenizer.scan_tokens_with_fuzzy_recovery().unwrap();
+
+        let kinds: Vec<_> = warnings.iter().map(Error::kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &ErrorKind::InsertedToken { lexeme: "}" },
+                &ErrorKind::InsertedToken { lexeme: ")" },
+            ]
+        );
+        assert_eq!(warnings[1].message(), "inserted missing `)`");
+
+        let closer = &tokens[tokens.len() - 2];
+        assert_eq!(closer.kind(), &TokenKind::Bracket(")"));
+        assert!(closer.is_synthetic());
+    }
+
+    #[test]
+    fn error_budget() {
+        let source = "1 # 2 # 3";
+
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let errors = tokenizer
+            .scan_tokens_with_error_budget(Some(1))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let (tokens, errors) = tokenizer.scan_tokens_with_error_budget(None).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(tokens.len(), 4);
+
+        let mut tokenizer = Tokenizer::new("a #", "test");
+        let (_, errors) = tokenizer.scan_tokens_with_error_budget(Some(1)).unwrap();
+        assert_eq!(errors.len(), 1);
+
+        let mut tokenizer = Tokenizer::new("a # b", "test");
+        let errors = tokenizer
+            .scan_tokens_with_error_budget(Some(0))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn string_literal_round_trip() {
+        let source = "\"hello, world\"";
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+        let contents = &source[1..source.len() - 1];
+
+        assert_eq!(tokens[0].lexeme(), source);
+        assert_eq!(
+            kinds(source, TokenizerConfig::default()),
+            [TokenKind::String(contents.to_owned())]
+        );
+    }
+
+    #[test]
+    fn keyword_is_not_matched_as_a_prefix() {
+        let config = TokenizerConfig {
+            keywords: vec!["let".to_owned()],
+            ..TokenizerConfig::default()
+        };
+
+        assert_eq!(
+            kinds("let letter", config),
+            [TokenKind::Keyword("let"), TokenKind::Identifier("letter")]
+        );
+    }
+
+    #[test]
+    fn source_ending_in_lone_minus() {
+        assert_eq!(
+            kinds("a -", TokenizerConfig::default()),
+            [TokenKind::Identifier("a"), TokenKind::Operator("-")]
+        );
+        assert_eq!(
+            kinds("-", TokenizerConfig::default()),
+            [TokenKind::Operator("-")]
+        );
+    }
+
+    #[test]
+    fn unclosed_block_comment_does_not_skip_input() {
+        let mut tokenizer = Tokenizer::new("{-foo", "test");
+        let error = tokenizer.scan_tokens().unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::UnterminatedBlockComment);
+        assert_eq!(error.span(), Span::new("{-foo", 0, 1, 1));
+    }
+
+    #[test]
+    fn tokens_and_errors_compare_whole() {
+        let source = "a + 1";
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let tokens = tokenizer.scan_tokens().unwrap().to_vec();
+
+        assert_eq!(
+            tokens[0],
+            Token::from_range_inclusive(source, 0, 0, 1, 1, TokenKind::Identifier("a"))
+        );
+        assert_eq!(
+            tokens[2],
+            Token::from_range_inclusive(source, 4, 4, 1, 5, TokenKind::Number(1.0))
+        );
+        assert_eq!(
+            tokens,
+            Tokenizer::new(source, "test").scan_tokens().unwrap()
+        );
+
+        let error = Tokenizer::new("1 #", "test").scan_tokens().unwrap_err();
+        assert_eq!(
+            error,
+            Tokenizer::new("1 #", "test").scan_tokens().unwrap_err()
+        );
+        assert_ne!(
+            error,
+            Tokenizer::new("1 #", "other").scan_tokens().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn signed_exponents() {
+        assert_eq!(
+            kinds("1e+5 1e-5 1e5", TokenizerConfig::default()),
+            [
+                TokenKind::Number(100000.0),
+                TokenKind::Number(0.00001),
+                TokenKind::Number(100000.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn boolean_literals() {
+        assert_eq!(
+            kinds("truefalse", TokenizerConfig::default()),
+            [TokenKind::Identifier("truefalse")]
+        );
+        assert_eq!(
+            kinds("true false", TokenizerConfig::default()),
+            [TokenKind::Boolean(true), TokenKind::Boolean(false)]
+        );
+    }
+
+    #[test]
+    fn null_literal() {
+        assert_eq!(
+            kinds("null nullable", TokenizerConfig::default()),
+            [TokenKind::Null, TokenKind::Identifier("nullable")]
+        );
+    }
+
+    #[test]
+    fn custom_operators() {
+        let config = || TokenizerConfig {
+            allow_custom_ops: true,
+            ..TokenizerConfig::default()
+        };
+
+        for op in ["|>", "<|>", ">>=", "<*>"] {
+            assert_eq!(kinds(op, config()), [TokenKind::CustomOp(op.to_owned())]);
+        }
+        assert_eq!(
+            kinds("f <*> x", config()),
+            [
+                TokenKind::Identifier("f"),
+                TokenKind::CustomOp("<*>".to_owned()),
+                TokenKind::Identifier("x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn compact_bytes_round_trip() {
+        let source = "x = \"héllo\" + 0x1F";
+        let mut tokenizer = Tokenizer::new(source, "test");
+
+        for token in tokenizer.scan_tokens().unwrap() {
+            let bytes = token.to_compact_bytes().unwrap();
+            let decoded = OwnedToken::from_compact_bytes(bytes, source).unwrap();
+
+            assert_eq!(decoded.lexeme, token.lexeme());
+            assert_eq!(decoded.kind, OwnedTokenKind::from(token.kind()));
+            assert_eq!(
+                (decoded.line, decoded.column),
+                (token.line(), token.column())
+            );
+        }
+    }
+
+    #[test]
+    fn compact_bytes_reject_bad_records() {
+        let source = "\"é\"";
+        let mut tokenizer = Tokenizer::new(source, "test");
+        let bytes = tokenizer.scan_tokens().unwrap()[0]
+            .to_compact_bytes()
+            .unwrap();
+
+        assert_eq!(
+            OwnedToken::from_compact_bytes([99; 16], source).unwrap_err(),
+            CompactError::UnsupportedVersion(99)
+        );
+
+        let mut unknown = bytes;
+        unknown[0] = 99;
+        assert_eq!(
+            OwnedToken::from_compact_bytes(unknown, source).unwrap_err(),
+            CompactError::UnknownKind(99)
+        );
+
+        let mut split_char = bytes;
+        split_char[1] = 2;
+        assert_eq!(
+            OwnedToken::from_compact_bytes(split_char, source).unwrap_err(),
+            CompactError::LexemeOutOfBounds {
+                offset: 0,
+                length: 2
+            }
+        );
+        assert!(OwnedToken::from_compact_bytes(bytes, "xxxx").is_err());
+
+        let long = format!("\"{}\"", "a".repeat(70_000));
+        let mut tokenizer = Tokenizer::new(&long, "test");
+        assert_eq!(
+            tokenizer.scan_tokens().unwrap()[0].to_compact_bytes(),
+            Err(CompactError::FieldOverflow {
+                field: "lexeme length",
+                value: 70_002
+            })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn streaming_to_file_rejects_oversized_tokens() {
+        let path = std::env::temp_dir().join("espritc-oversized-token.bin");
+        let long = format!("x \"{}\"", "a".repeat(70_000));
+
+        let result = Tokenizer::new(&long, "test").scan_tokens_streaming_to_file(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(
+            result,
+            Err(ScanIoError::Compact(CompactError::FieldOverflow {
+                field: "lexeme length",
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn heredoc_support_is_scoped_to_the_call() {
+        let mut tokenizer = Tokenizer::new("x <<END\nhello\nEND\n", "test");
+        let tokens = tokenizer.scan_tokens_with_heredoc_support().unwrap();
+        assert_eq!(
+            tokens[1].kind(),
+            &TokenKind::HeredocLiteral {
+                tag: "END".to_owned(),
+                content: "hello".to_owned(),
+            }
+        );
+
+        tokenizer.reset("a <<b");
+        assert!(tokenizer.scan_tokens().is_ok());
+    }
+
+    #[test]
+    fn per_call_settings_do_not_leak() {
+        let mut tokenizer = Tokenizer::new("abcdef", "test");
+        assert!(tokenizer.scan_tokens_checking_identifier_length(3).is_err());
+        tokenizer.reset("abcdef");
+        assert!(tokenizer.scan_tokens().is_ok());
+
+        tokenizer.reset("1");
+        tokenizer.scan_tokens_type_annotated().unwrap();
+        tokenizer.reset("1");
+        assert_eq!(tokenizer.scan_tokens().unwrap()[0].type_hint, None);
+
+        tokenizer.reset("a");
+        tokenizer
+            .scan_tokens_with_taint_tracking(TaintLevel::Untrusted)
+            .unwrap();
+        tokenizer.reset("a");
+        assert_eq!(
+            tokenizer.scan_tokens().unwrap()[0].taint,
+            TaintLevel::Trusted
+        );
+
+        tokenizer.reset("a b");
+        tokenizer.scan_tokens_preserving_formatting().unwrap();
+        tokenizer.reset("a b");
+        assert_eq!(tokenizer.scan_tokens().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn interactive_scan_can_be_repeated_after_reset() {
+        let mut tokenizer = Tokenizer::new("f(\"a", "test");
+        assert!(matches!(
+            tokenizer.scan_tokens_line_by_line_interactive(),
+            ScanLineResult::Incomplete {
+                reason: IncompleteReason::UnterminatedString
+            }
+        ));
+
+        tokenizer.reset("f(\"a\")");
+        match tokenizer.scan_tokens_line_by_line_interactive() {
+            ScanLineResult::Complete(tokens) => assert_eq!(tokens.len(), 5),
+            other => panic!("expected a complete line, got {:?}", other),
+        }
+
+        tokenizer.reset("--- doc\na");
+        let (_, comments) = tokenizer.scan_tokens_with_comment_map().unwrap();
+        assert_eq!(comments.get(0).map(Token::lexeme), Some("a"));
+        tokenizer.reset("--- doc\na");
+        assert_eq!(tokenizer.scan_tokens().unwrap().len(), 2);
+
+        tokenizer.reset("(a");
+        assert_eq!(
+            tokenizer.scan_tokens_with_fuzzy_recovery().unwrap().1.len(),
+            1
+        );
+        tokenizer.reset("a;");
+        assert_eq!(
+            tokenizer
+                .scan_tokens_annotated_with_semantic_groups()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn span_table_stream_reports_source_in_errors() {
+        let mut tokenizer = Tokenizer::new("a b", "main.es");
+        let (mut stream, _) = tokenizer.scan_tokens_with_span_table().unwrap();
+
+        stream.advance();
+        let rendered = stream
+            .expect(TokenKind::Operator("+"))
+            .unwrap_err()
+            .to_plain_string();
+
+        assert!(rendered.contains("--> main.es:1:3"), "{}", rendered);
+        assert!(rendered.contains("a b"), "{}", rendered);
+    }
+
+    #[test]
+    fn heredoc_forms() {
+        let config = || TokenizerConfig {
+            heredocs: true,
+            ..TokenizerConfig::default()
+        };
+        let heredoc = |content: &str| TokenKind::HeredocLiteral {
+            tag: "END".to_owned(),
+            content: content.to_owned(),
+        };
+
+        assert_eq!(
+            kinds("x <<END\nhello\nworld\nEND\ny", config()),
+            [
+                TokenKind::Identifier("x"),
+                heredoc("hello\nworld"),
+                TokenKind::Identifier("y"),
+            ]
+        );
+        assert_eq!(
+            kinds("<<-END\n    hello\n    END", config()),
+            [heredoc("hello")]
+        );
+        assert_eq!(kinds("<<END\nhello\nEND;", config()), [heredoc("hello")]);
+        assert_eq!(
+            kinds("x <<END\r\nhello\r\nEND\r\ny", config()),
+            [
+                TokenKind::Identifier("x"),
+                heredoc("hello"),
+                TokenKind::Identifier("y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_cache_is_keyed_on_config() {
+        let mut cache = TokenCache::new();
+
+        Tokenizer::new("let", "test")
+            .scan_tokens_cached(&mut cache)
+            .unwrap();
+        assert_eq!(
+            cache.tokens()[0].kind,
+            OwnedTokenKind::Identifier("let".to_owned())
+        );
+
+        let config = TokenizerConfig {
+            keywords: vec!["let".to_owned()],
+            ..TokenizerConfig::default()
+        };
+        let tokens = Tokenizer::with_config("let", "test", config)
+            .scan_tokens_cached(&mut cache)
+            .unwrap();
+        assert_eq!(tokens[0].kind, OwnedTokenKind::Keyword("let".to_owned()));
+    }
+
+    #[test]
+    fn indented_blank_lines_join_the_newline_run() {
+        let config = TokenizerConfig {
+            preserve_whitespace: true,
+            ..TokenizerConfig::default()
+        };
+        let source = "a\n  \n\tb";
+        let mut tokenizer = Tokenizer::with_config(source, "test", config);
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind().clone()).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Identifier("a"),
+                TokenKind::Newline(2),
+                TokenKind::Whitespace,
+                TokenKind::Identifier("b"),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[1].lexeme(), "\n  \n");
+        assert_eq!((tokens[3].line(), tokens[3].column()), (3, 2));
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(
+            kinds("1_000_000 0xFF_FF 1_000n 0b1_0", TokenizerConfig::default()),
+            [
+                TokenKind::Number(1_000_000.0),
+                TokenKind::Number(65535.0),
+                TokenKind::BigInt(BigInt::from(1000)),
+                TokenKind::Number(2.0),
+            ]
+        );
+
+        for source in ["1_", "1__0", "0b_", "0x1_", "1.5_", "1e5_"] {
+            let error = Tokenizer::new(source, "test").scan_tokens().unwrap_err();
+            assert_eq!(error.kind(), &ErrorKind::ExpectedDigit, "{}", source);
+        }
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        let mut tokenizer = Tokenizer::new("\"héllo\" x", "test");
+        let tokens = tokenizer.scan_tokens().unwrap();
+
+        assert_eq!(tokens[0].kind(), &TokenKind::String("héllo".to_owned()));
+        assert_eq!((tokens[1].line(), tokens[1].column()), (1, 9));
+
+        let mut tokenizer = Tokenizer::new("a é", "test");
+        let error = tokenizer.scan_tokens().unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::UnknownCharacter);
+        assert_eq!(error.span(), Span::new("é", 2, 1, 3));
+    }
+
+    #[test]
+    fn incomplete_exponent() {
+        for (source, suggestion) in [("1e", "1e0"), ("1e+", "1e+0"), ("1e-", "1e-0")] {
+            let error = Tokenizer::new(source, "test").scan_tokens().unwrap_err();
+
+            assert_eq!(error.kind(), &ErrorKind::IncompleteExponent);
+            assert!(error
+                .to_plain_string()
+                .contains(&format!("did you mean `{}`?", suggestion)));
+        }
+    }
 }